@@ -0,0 +1,160 @@
+use core::fmt;
+use typst::syntax::{Source, Span};
+
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A message about a span of the source, printed in the style of rustc.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    /// Suggestions printed after the excerpt.
+    pub hints: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            span,
+            hints: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message, span)
+        }
+    }
+
+    pub fn with_hints(mut self, hints: Vec<String>) -> Self {
+        self.hints = hints;
+        self
+    }
+
+    /// Renders the message with `path:line:col` and the underlined line of `source`.
+    pub fn render(&self, path: &str, source: &Source) -> String {
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+        let (gutter, excerpt) = match excerpt(path, source, self.span) {
+            Some((gutter, excerpt)) => (gutter, excerpt),
+            None => (" ".to_string(), format!(" --> {}\n", path)),
+        };
+        out += &excerpt;
+        for hint in &self.hints {
+            out += &format!("{} = hint: {}\n", gutter, hint);
+        }
+        out
+    }
+}
+
+/// The location of `span` and its line with the span underlined, and the gutter before `|`.
+fn excerpt(path: &str, source: &Source, span: Span) -> Option<(String, String)> {
+    let range = source.range(span)?;
+    let line = source.byte_to_line(range.start)?;
+    let column = source.byte_to_column(range.start)?;
+    let line_range = source.line_to_range(line)?;
+    let text = source.text()[line_range.clone()].trim_end_matches(['\r', '\n']);
+    let head = &source.text()[line_range.start..range.start];
+    // a span over several lines is underlined up to the end of its first line
+    let end = range
+        .end
+        .min(line_range.start + text.len())
+        .max(range.start);
+    let marked = &source.text()[range.start..end];
+
+    let number = (line + 1).to_string();
+    let gutter = " ".repeat(number.len());
+    let mut out = format!("{}--> {}:{}:{}\n", gutter, path, line + 1, column + 1);
+    out += &format!("{} |\n", gutter);
+    out += &format!("{} | {}\n", number, text);
+    out += &format!(
+        "{} | {}{}\n",
+        gutter,
+        head.chars()
+            .map(|c| if c == '\t' { "\t" } else { spaces(c) })
+            .collect::<String>(),
+        "^".repeat(width(marked).max(1)),
+    );
+    Some((gutter, out))
+}
+
+/// The number of terminal columns taken by `s`, counting East Asian wide characters as two.
+fn width(s: &str) -> usize {
+    s.chars().map(|c| spaces(c).len()).sum()
+}
+
+fn spaces(c: char) -> &'static str {
+    if matches!(c as u32,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x20000..=0x3FFFD)
+    {
+        "  "
+    } else {
+        " "
+    }
+}
+
+#[test]
+fn _test_render() {
+    use crate::mathlog::from_typst::{convert_source, FromTypstOptions};
+
+    let source = Source::detached("= 定理\n\n定理 #link(\"a\", foo: 1)[b]\n");
+    let err = convert_source(&source, FromTypstOptions::default()).unwrap_err();
+    let diagnostic = Diagnostic::error(err.kind.to_string(), err.span);
+    assert_eq!(
+        diagnostic.render("main.typ", &source),
+        "error: unknown named argument `foo` of `link`\n \
+         --> main.typ:3:15\n  \
+         |\n\
+         3 | 定理 #link(\"a\", foo: 1)[b]\n  \
+         |                 ^^^^^^\n"
+    );
+}
+
+#[test]
+fn _test_render_hints() {
+    let source = Source::detached("#for x, y in () {}\n");
+    let errors = source.root().errors();
+    let [err] = &errors[..] else {
+        panic!("expected one error: {:?}", errors);
+    };
+    let hints = err.hints.iter().map(|hint| hint.to_string()).collect();
+    let diagnostic = Diagnostic::error(err.message.to_string(), err.span).with_hints(hints);
+    assert_eq!(
+        diagnostic.render("main.typ", &source),
+        "error: unexpected comma\n \
+         --> main.typ:1:7\n  \
+         |\n\
+         1 | #for x, y in () {}\n  \
+         |       ^\n  \
+         = hint: destructuring patterns must be wrapped in parentheses\n"
+    );
+}
@@ -1,3 +1,4 @@
+pub mod diagnostic;
 pub mod mathlog;
 pub mod utils;
 
@@ -8,41 +9,110 @@ use typst::syntax::ast::AstNode;
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     use typst::syntax;
 
-    let current_exe = std::env::current_exe()?.to_str().unwrap().to_string();
+    let current_exe = std::env::current_exe()?;
 
-    let dictionary_path = "../../dictionary/dictionary.json".to_string();
-    let dictionary_path = current_exe + "\\" + &*dictionary_path;
+    let dictionary_path = current_exe
+        .ancestors()
+        .nth(2)
+        .ok_or("cannot locate the dictionary")?
+        .join("dictionary/dictionary.json");
 
     let dic = mathlog::Dictionary::read(&dictionary_path)?;
 
-    let current_dir = std::env::current_dir()?.to_str().unwrap().to_string();
+    let current_dir = std::env::current_dir()?;
 
-    let input_path = std::env::args().nth(1).expect("No input path");
-    // let input_path = "./example/example.typ".to_string();
-    let input_path = if input_path.starts_with("C:\\") || input_path.starts_with("D:\\") {
-        input_path
-    } else {
-        current_dir.clone() + "\\" + &*input_path
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    // copy referenced images into `assets` next to the output and rewrite their paths
+    let copy_assets = args.iter().any(|arg| arg == "--copy-assets");
+    // report every error and leave the failed parts in comments instead of stopping at the first
+    let lenient = args.iter().any(|arg| arg == "--lenient");
+    // fail on warnings, which are lossy conversions
+    let deny_warnings = args.iter().any(|arg| arg == "--deny-warnings");
+    // `auto`, `text` or `mathrm`: how strings in math are written
+    let math_text = match args.iter().find_map(|arg| arg.strip_prefix("--math-text=")) {
+        Some(mode) => mode.parse()?,
+        None => mathlog::compose::MathTextMode::default(),
     };
-    let input = std::fs::read_to_string(input_path)?;
+    let mut paths = args.iter().filter(|arg| !arg.starts_with("--"));
+
+    let input_arg = paths.next().expect("No input path");
+    // let input_path = "./example/example.typ".to_string();
+    let input_path = current_dir.join(input_arg);
+    let input = std::fs::read_to_string(&input_path)?;
 
-    let output_path = std::env::args().nth(2).expect("No output path");
+    let output_path = paths.next().expect("No output path");
     // let output_path = "./example/example.md".to_string();
-    let output_path = if output_path.starts_with("C:\\") || output_path.starts_with("D:\\") {
-        output_path
-    } else {
-        current_dir + "\\" + &*output_path
+    let output_path = current_dir.join(output_path);
+
+    let source = syntax::Source::detached(input);
+    // the parser leaves errors in the tree, which would be converted into garbage
+    let syntax_errors = source.root().errors();
+    if !syntax_errors.is_empty() {
+        for err in &syntax_errors {
+            let hints = err.hints.iter().map(|hint| hint.to_string()).collect();
+            let diagnostic =
+                diagnostic::Diagnostic::error(err.message.to_string(), err.span).with_hints(hints);
+            eprint!("{}", diagnostic.render(input_arg, &source));
+        }
+        std::process::exit(1);
+    }
+    let typst_stx =
+        syntax::ast::Markup::from_untyped(source.root()).ok_or("parse error in Typst code")?;
+
+    let report = |diagnostic: diagnostic::Diagnostic| {
+        eprint!("{}", diagnostic.render(input_arg, &source));
     };
 
-    let binding = syntax::parse(&input);
-    let typst_stx = syntax::ast::Markup::from_untyped(&binding)
-        .ok_or("parse error in Typst code")?;
+    let options = mathlog::from_typst::FromTypstOptions { lenient };
+    let output = match mathlog::ast::Syntax::from_typst_with(&typst_stx, &dic, options) {
+        Ok(output) => output,
+        Err(err) => {
+            report(diagnostic::Diagnostic::error(
+                err.kind.to_string(),
+                err.span,
+            ));
+            std::process::exit(1);
+        }
+    };
+    let mut diagnostics = output
+        .errors
+        .iter()
+        .map(|err| diagnostic::Diagnostic::error(err.kind.to_string(), err.span))
+        .chain(output.warnings.iter().map(|warning| {
+            let message = warning.kind.to_string();
+            if deny_warnings {
+                diagnostic::Diagnostic::error(message, warning.span)
+            } else {
+                diagnostic::Diagnostic::warning(message, warning.span)
+            }
+        }))
+        .collect::<Vec<_>>();
+    diagnostics.sort_by_key(|diagnostic| source.range(diagnostic.span).map(|range| range.start));
+    let failures = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == diagnostic::Severity::Error)
+        .count();
+    diagnostics.into_iter().for_each(report);
+    // only lenient mode writes the output despite errors
+    if failures > 0 && !lenient {
+        std::process::exit(1);
+    }
+    let mut mathlog_stx = output.syntax;
 
-    let mathlog_stx = mathlog::ast::Syntax::from_typst(&typst_stx, &dic)?;
+    if copy_assets {
+        let source_dir = input_path.parent().unwrap_or(&current_dir);
+        let output_dir = output_path.parent().unwrap_or(&current_dir);
+        mathlog_stx.copy_assets(source_dir, &output_dir.join("assets"))?;
+    }
 
-    let output = mathlog_stx.compose();
+    let output = mathlog_stx.compose_with(mathlog::compose::ComposeOptions { math_text });
 
     std::fs::write(output_path, output)?;
 
+    if failures > 0 {
+        eprintln!("{} errors", failures);
+        std::process::exit(1);
+    }
+
     Ok(())
 }
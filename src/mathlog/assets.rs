@@ -0,0 +1,102 @@
+use super::ast::*;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+//
+
+impl Syntax {
+    /// Copies every image referred to into `assets_dir` and rewrites the paths to point there.
+    /// Paths in the source are relative to `source_dir`; the rewritten ones are relative to
+    /// the parent directory of `assets_dir`, i.e. the directory of the output.
+    pub fn copy_assets(&mut self, source_dir: &Path, assets_dir: &Path) -> std::io::Result<()> {
+        let dir_name = assets_dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let mut copied = HashMap::<PathBuf, String>::new();
+        let mut result = Ok(());
+        self.visit_mut(&mut |segment| {
+            let Segment::Image(image) = segment else {
+                return;
+            };
+            if result.is_err() {
+                return;
+            }
+            // Typst resolves paths starting with `/` against the project root
+            let source = source_dir.join(image.path.trim_start_matches('/'));
+            if let Some(path) = copied.get(&source) {
+                image.path = path.clone();
+                return;
+            }
+            let file_name = asset_file_name(&source, copied.len());
+            result = std::fs::create_dir_all(assets_dir)
+                .and_then(|_| std::fs::copy(&source, assets_dir.join(&file_name)))
+                // the bare error does not say which file is missing
+                .map_err(|err| {
+                    std::io::Error::new(err.kind(), format!("{}: {}", source.display(), err))
+                })
+                .map(|_| {
+                    let path = format!("{}/{}", dir_name, file_name);
+                    copied.insert(source, path.clone());
+                    image.path = path;
+                });
+        });
+        result
+    }
+}
+
+/// Prefixes the file name with the number of assets copied so far,
+/// so that files of the same name in different directories do not collide.
+fn asset_file_name(source: &Path, index: usize) -> String {
+    let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+    format!("{}-{}", index, file_name)
+}
+
+#[test]
+fn _test_copy_assets() {
+    let dir = std::env::temp_dir().join(format!("typst-to-mathlog-{}", std::process::id()));
+    let source_dir = dir.join("src");
+    std::fs::create_dir_all(source_dir.join("sub")).unwrap();
+    std::fs::write(source_dir.join("a.png"), "a").unwrap();
+    std::fs::write(source_dir.join("sub/a.png"), "sub/a").unwrap();
+    let image = |path: &str| {
+        Segment::Image(Image {
+            path: path.to_string(),
+            alt: None,
+        })
+    };
+    let mut syntax = Syntax {
+        paragraphs: vec![Paragraph {
+            segments: Segments(vec![image("a.png"), image("/a.png"), image("sub/a.png")]),
+        }],
+    };
+    let assets_dir = dir.join("out/assets");
+    syntax.copy_assets(&source_dir, &assets_dir).unwrap();
+    let mut paths = vec![];
+    syntax.visit_mut(&mut |segment| {
+        if let Segment::Image(image) = segment {
+            paths.push(image.path.clone());
+        }
+    });
+    assert_eq!(
+        paths,
+        ["assets/0-a.png", "assets/0-a.png", "assets/1-a.png"]
+    );
+    assert_eq!(
+        std::fs::read_to_string(assets_dir.join("1-a.png")).unwrap(),
+        "sub/a"
+    );
+
+    let mut syntax = Syntax {
+        paragraphs: vec![Paragraph {
+            segments: Segments(vec![image("missing.png")]),
+        }],
+    };
+    let err = syntax.copy_assets(&source_dir, &assets_dir).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with(&source_dir.join("missing.png").display().to_string()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,10 +1,25 @@
+use crate::utils::{numbering, numbering_pattern};
+
 #[derive(Debug, Clone)]
 pub struct Syntax {
     pub paragraphs: Paragraphs,
 }
 
+impl Syntax {
+    /// Visits every segment in document order, parents before their children.
+    pub fn visit_mut<F: FnMut(&mut Segment)>(&mut self, f: &mut F) {
+        visit_paragraphs_mut(&mut self.paragraphs, f);
+    }
+}
+
 pub type Paragraphs = Vec<Paragraph>;
 
+fn visit_paragraphs_mut<F: FnMut(&mut Segment)>(paragraphs: &mut Paragraphs, f: &mut F) {
+    for paragraph in paragraphs {
+        paragraph.segments.visit_mut(f);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Paragraph {
     pub segments: Segments,
@@ -25,6 +40,13 @@ impl Segments {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    pub fn visit_mut<F: FnMut(&mut Segment)>(&mut self, f: &mut F) {
+        for segment in &mut self.0 {
+            f(segment);
+            segment.visit_children_mut(f);
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -34,25 +56,157 @@ pub enum Segment {
     Heading(Heading),
     Text(Text),
     CodeInline(CodeInline),
+    CodeBlock(CodeBlock),
     Strong(Strong),
     Emph(Emph),
     MathInline(MathInline),
     MathDisplay(MathDisplay),
     ListItem(ListItem),
+    TermItem(TermItem),
     MathDelimited(MathDelimited),
+    /// A delimiter in the middle of [`MathDelimited`] such as `\middle|`.
+    MathMiddle(Segments),
     MathAttach(MathAttach),
+    MathEnv(MathEnv),
+    /// A string in math such as `"if"`, written by `\text` or `\mathrm`.
+    MathText(String),
     MathAlignPoint,
     Command(Command),
     RawCommand(RawCommand),
     Env(Env),
+    Link(Link),
+    Footnote(Footnote),
+    Quote(Quote),
+    Table(Table),
+    Image(Image),
+    Figure(Figure),
+    Label(String),
+    Ref(Ref),
     ExportComment(String),
+    /// A comment within a line, left in place of an inline node which failed to convert.
+    InlineComment(String),
+    /// The source of a math node which failed to convert, shown as unsupported.
+    MathPlaceholder(String),
     // TODO
 }
 
+impl Segment {
+    fn visit_children_mut<F: FnMut(&mut Segment)>(&mut self, f: &mut F) {
+        match self {
+            Segment::Heading(heading) => heading.content.visit_mut(f),
+            Segment::Strong(strong) => strong.content.visit_mut(f),
+            Segment::Emph(emph) => emph.content.visit_mut(f),
+            Segment::MathInline(math_inline) => math_inline.content.visit_mut(f),
+            Segment::MathDisplay(math_display) => math_display.content.visit_mut(f),
+            Segment::ListItem(list) => visit_paragraphs_mut(&mut list.contents, f),
+            Segment::TermItem(term) => {
+                term.term.visit_mut(f);
+                visit_paragraphs_mut(&mut term.description, f);
+            }
+            Segment::MathDelimited(math_delimited) => {
+                math_delimited.open.visit_mut(f);
+                math_delimited.body.visit_mut(f);
+                math_delimited.close.visit_mut(f);
+            }
+            Segment::MathMiddle(content) => content.visit_mut(f),
+            Segment::MathAttach(math_attach) => {
+                math_attach.base.visit_mut(f);
+                if let Some(bottom) = &mut math_attach.bottom {
+                    bottom.visit_mut(f);
+                }
+                if let Some(top) = &mut math_attach.top {
+                    top.visit_mut(f);
+                }
+                if let Some(top_left) = &mut math_attach.top_left {
+                    top_left.visit_mut(f);
+                }
+                if let Some(bottom_left) = &mut math_attach.bottom_left {
+                    bottom_left.visit_mut(f);
+                }
+            }
+            Segment::MathEnv(math_env) => {
+                for cell in math_env.rows.iter_mut().flatten() {
+                    cell.visit_mut(f);
+                }
+            }
+            Segment::Command(command) => {
+                for arg in &mut command.args {
+                    arg.content.visit_mut(f);
+                }
+            }
+            Segment::Env(env) => {
+                if let Some(title) = &mut env.title {
+                    title.visit_mut(f);
+                }
+                visit_paragraphs_mut(&mut env.contents, f);
+            }
+            Segment::Link(link) => {
+                if let Some(content) = &mut link.content {
+                    content.visit_mut(f);
+                }
+            }
+            Segment::Footnote(footnote) => footnote.content.visit_mut(f),
+            Segment::Quote(quote) => {
+                visit_paragraphs_mut(&mut quote.contents, f);
+                if let Some(attribution) = &mut quote.attribution {
+                    attribution.visit_mut(f);
+                }
+            }
+            Segment::Ref(ref_) => {
+                if let Some(supplement) = &mut ref_.supplement {
+                    supplement.visit_mut(f);
+                }
+            }
+            Segment::Table(table) => {
+                for cell in table
+                    .header
+                    .iter_mut()
+                    .chain(table.body.iter_mut().flatten())
+                {
+                    cell.visit_mut(f);
+                }
+            }
+            Segment::Figure(figure) => {
+                visit_paragraphs_mut(&mut figure.body, f);
+                if let Some(caption) = &mut figure.caption {
+                    caption.visit_mut(f);
+                }
+            }
+            Segment::Linebreak
+            | Segment::Text(_)
+            | Segment::CodeInline(_)
+            | Segment::CodeBlock(_)
+            | Segment::MathAlignPoint
+            | Segment::MathText(_)
+            | Segment::RawCommand(_)
+            | Segment::Image(_)
+            | Segment::Label(_)
+            | Segment::ExportComment(_)
+            | Segment::InlineComment(_)
+            | Segment::MathPlaceholder(_) => {}
+        }
+    }
+
+    /// The label slot of a segment which can be referred to by `@label`.
+    pub fn label_mut(&mut self) -> Option<&mut Option<String>> {
+        match self {
+            Segment::Heading(heading) => Some(&mut heading.label),
+            Segment::MathDisplay(math_display) => Some(&mut math_display.label),
+            Segment::Env(env) => Some(&mut env.label),
+            Segment::Figure(figure) => Some(&mut figure.label),
+            Segment::Footnote(footnote) => Some(&mut footnote.label),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Heading {
     pub depth: usize,
     pub content: Segments,
+    /// Whether `#set heading(numbering: ..)` numbers the heading.
+    pub numbered: bool,
+    pub label: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -61,6 +215,12 @@ pub struct Text(pub String);
 #[derive(Debug, Clone)]
 pub struct CodeInline(pub String);
 
+#[derive(Debug, Clone)]
+pub struct CodeBlock {
+    pub lang: Option<String>,
+    pub code: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct Strong {
     pub content: Segments,
@@ -79,6 +239,10 @@ pub struct MathInline {
 #[derive(Debug, Clone)]
 pub struct MathDisplay {
     pub content: Segments,
+    /// The numbering pattern set by `#set math.equation(numbering: ..)`.
+    pub numbering: Option<String>,
+    pub label: Option<String>,
+    pub number: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -96,6 +260,37 @@ pub enum ListSymbol {
     RomanDot(usize),
     RomanParen(usize),
     RomanBrak(usize),
+    RomanLowerDot(usize),
+    RomanLowerParen(usize),
+    RomanLowerBrak(usize),
+    /// A label of any other numbering pattern, such as `a)` or `(A)`.
+    Custom(String),
+}
+
+impl ListSymbol {
+    /// The symbol of the `number`-th item of an enumeration by a Typst numbering pattern.
+    /// Returns `None` if the pattern is invalid.
+    pub fn from_numbering(pattern: &str, depth: usize, number: usize) -> Option<Self> {
+        let (prefix, symbol, suffix) = numbering_pattern(pattern, depth)?;
+        Some(match (&*prefix, symbol, &*suffix) {
+            ("", '1', ".") => ListSymbol::NumDot(number),
+            ("(", '1', ")") => ListSymbol::NumParen(number),
+            ("[", '1', "]") => ListSymbol::NumBrak(number),
+            ("", 'I', ".") => ListSymbol::RomanDot(number),
+            ("(", 'I', ")") => ListSymbol::RomanParen(number),
+            ("[", 'I', "]") => ListSymbol::RomanBrak(number),
+            ("", 'i', ".") => ListSymbol::RomanLowerDot(number),
+            ("(", 'i', ")") => ListSymbol::RomanLowerParen(number),
+            ("[", 'i', "]") => ListSymbol::RomanLowerBrak(number),
+            _ => ListSymbol::Custom(format!("{}{}{}", prefix, numbering(symbol, number), suffix)),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TermItem {
+    pub term: Segments,
+    pub description: Paragraphs,
 }
 
 #[derive(Debug, Clone)]
@@ -103,6 +298,23 @@ pub struct MathDelimited {
     pub open: Segments,
     pub body: Segments,
     pub close: Segments,
+    pub size: DelimiterSize,
+}
+
+/// The size of delimiters: fitted to the body by `\left`/`\right`, or fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DelimiterSize {
+    #[default]
+    Auto,
+    Normal,
+    /// `\bigl`, `\bigm` and `\bigr`
+    Big,
+    /// `\Bigl`, `\Bigm` and `\Bigr`
+    BigCap,
+    /// `\biggl`, `\biggm` and `\biggr`
+    Bigg,
+    /// `\Biggl`, `\Biggm` and `\Biggr`
+    BiggCap,
 }
 
 #[derive(Debug, Clone)]
@@ -110,6 +322,23 @@ pub struct MathAttach {
     pub base: Segments,
     pub top: Option<Segments>,
     pub bottom: Option<Segments>,
+    /// Scripts before the base, written as `{}^{a}_{b}X`.
+    pub top_left: Option<Segments>,
+    pub bottom_left: Option<Segments>,
+    pub primes: usize,
+    /// `\limits` or `\nolimits` after the base.
+    pub limits: Option<bool>,
+}
+
+/// A LaTeX environment in math such as `pmatrix` or `cases`, with cells separated by `&`.
+#[derive(Debug, Clone)]
+pub struct MathEnv {
+    pub name: String,
+    /// e.g. the column specification `{cc|c}` of `array`.
+    pub column_spec: Option<String>,
+    pub rows: Vec<Vec<Segments>>,
+    /// Indices of the rows preceded by `\hline`.
+    pub hlines: Vec<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -117,6 +346,7 @@ pub struct Env {
     pub kind: EnvKind,
     pub title: Option<Segments>,
     pub contents: Paragraphs,
+    pub label: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -161,12 +391,177 @@ impl EnvKind {
             .unwrap_or_else(|| panic!("unexpected env kind: {:?}", self))
     }
 
+    /// The name in Typst for messages, since the name of `Block` in Mathlog is empty.
+    pub fn typst_name(&self) -> String {
+        match self {
+            EnvKind::Block => "bl".to_string(),
+            _ => self.name(),
+        }
+    }
+
+    /// Names of environments in Typst other than their names in Mathlog.
+    pub const ALIASES: [(EnvKind, &'static str); 14] = [
+        (EnvKind::Block, "bl"),
+        (EnvKind::Conj, "conjecture"),
+        (EnvKind::Axm, "axiom"),
+        (EnvKind::Def, "definition"),
+        (EnvKind::Prop, "proposition"),
+        (EnvKind::Fml, "formula"),
+        (EnvKind::Lem, "lemma"),
+        (EnvKind::Thm, "theorem"),
+        (EnvKind::Cor, "corollary"),
+        (EnvKind::Prf, "proof"),
+        (EnvKind::Ex, "example"),
+        (EnvKind::Exc, "exercise"),
+        (EnvKind::Exc, "problem"),
+        (EnvKind::Rem, "remark"),
+    ];
+
     pub fn from_name(name: &str) -> Option<Self> {
+        if name.is_empty() {
+            return None;
+        }
         Self::LIST
             .iter()
+            .chain(Self::ALIASES.iter())
             .find(|(_, n)| n == &name)
             .map(|(kind, _)| kind.clone())
     }
+
+    /// The name of the counter shared by the environment, following `style/mathlog_style.typ`.
+    /// The environments it does not declare, such as `cor` and `axm`, are not numbered.
+    pub fn counter(&self) -> Option<&'static str> {
+        match self {
+            EnvKind::Def => Some("def"),
+            EnvKind::Thm | EnvKind::Prop | EnvKind::Lem => Some("thm"),
+            EnvKind::Ex => Some("ex"),
+            EnvKind::Exc => Some("exc"),
+            EnvKind::Fml => Some("fml"),
+            EnvKind::Block
+            | EnvKind::Conj
+            | EnvKind::Axm
+            | EnvKind::Cor
+            | EnvKind::Prf
+            | EnvKind::Rem => None,
+        }
+    }
+
+    pub fn supplement(&self) -> &'static str {
+        match self {
+            EnvKind::Block => "",
+            EnvKind::Conj => "予想",
+            EnvKind::Axm => "公理",
+            EnvKind::Def => "定義",
+            EnvKind::Prop => "命題",
+            EnvKind::Fml => "公式",
+            EnvKind::Lem => "補題",
+            EnvKind::Thm => "定理",
+            EnvKind::Cor => "系",
+            EnvKind::Prf => "証明",
+            EnvKind::Ex => "例",
+            EnvKind::Exc => "問題",
+            EnvKind::Rem => "注意",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub dest: LinkDest,
+    /// `None` for auto-links, which show the destination itself.
+    pub content: Option<Segments>,
+    /// The element a label destination refers to.
+    pub resolved: Option<RefTarget>,
+}
+
+#[derive(Debug, Clone)]
+pub enum LinkDest {
+    Url(String),
+    Label(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Footnote {
+    pub content: Segments,
+    pub label: Option<String>,
+    pub number: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Quote {
+    pub block: bool,
+    /// Whether to enclose the contents in quotation marks.
+    pub quotes: bool,
+    pub contents: Paragraphs,
+    pub attribution: Option<Segments>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub align: Vec<ColumnAlign>,
+    pub header: Vec<Segments>,
+    pub body: Vec<Vec<Segments>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnAlign {
+    Default,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub path: String,
+    pub alt: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Figure {
+    pub kind: FigureKind,
+    pub body: Paragraphs,
+    pub caption: Option<Segments>,
+    pub numbered: bool,
+    pub label: Option<String>,
+    pub number: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FigureKind {
+    Image,
+    Table,
+}
+
+impl FigureKind {
+    pub fn counter(&self) -> &'static str {
+        match self {
+            FigureKind::Image => "figure-image",
+            FigureKind::Table => "figure-table",
+        }
+    }
+
+    pub fn supplement(&self) -> &'static str {
+        match self {
+            FigureKind::Image => "図",
+            FigureKind::Table => "表",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ref {
+    pub target: String,
+    pub supplement: Option<Segments>,
+    pub resolved: Option<RefTarget>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RefTarget {
+    pub supplement: String,
+    pub number: String,
+    /// References to footnotes are footnote marks rather than text.
+    pub is_footnote: bool,
 }
 
 #[derive(Debug, Clone)]
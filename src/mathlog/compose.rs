@@ -1,18 +1,55 @@
 use super::ast::*;
-use crate::utils::roman;
+use crate::utils::{code_fence, roman};
+
+/// How strings in math are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MathTextMode {
+    /// `\text` for prose with spaces or non-ASCII letters, and `\mathrm` for identifiers.
+    #[default]
+    Auto,
+    Text,
+    Mathrm,
+}
+
+impl std::str::FromStr for MathTextMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "text" => Ok(Self::Text),
+            "mathrm" => Ok(Self::Mathrm),
+            _ => Err(format!("unknown math text mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ComposeOptions {
+    pub math_text: MathTextMode,
+}
 
 struct Composer {
-    indent: usize,
+    /// Written at the start of every new line: indentation and `> ` of block quotes.
+    prefixes: Vec<&'static str>,
     lines: Vec<String>,
     current_line: String,
+    /// Numbers and bodies of the footnotes, written at the end of the document.
+    footnotes: Vec<(String, String)>,
+    /// Sizes of the enclosing delimiters, used by middle delimiters.
+    delimiter_sizes: Vec<DelimiterSize>,
+    options: ComposeOptions,
 }
 
 impl Composer {
-    fn new() -> Self {
+    fn new(options: ComposeOptions) -> Self {
         Self {
-            indent: 0,
+            options,
+            prefixes: Vec::new(),
             lines: Vec::new(),
             current_line: String::new(),
+            footnotes: Vec::new(),
+            delimiter_sizes: Vec::new(),
         }
     }
 
@@ -21,25 +58,51 @@ impl Composer {
     }
 
     fn indent(&mut self) {
-        self.indent += 1;
+        self.prefixes.push("    ");
     }
 
     fn dedent(&mut self) {
-        self.indent -= 1;
+        self.prefixes.pop();
+    }
+
+    fn push_prefix(&mut self, prefix: &'static str) {
+        self.prefixes.push(prefix);
+    }
+
+    fn pop_prefix(&mut self) {
+        self.prefixes.pop();
+    }
+
+    fn prefix(&self) -> String {
+        self.prefixes.concat()
     }
 
     fn newline(&mut self) {
         self.lines.push(std::mem::take(&mut self.current_line));
-        self.current_line = String::new();
-        self.current_line.push_str(&"    ".repeat(self.indent));
+        self.current_line = self.prefix();
     }
 
     fn newline_if_not_empty(&mut self) {
-        if !self.current_line.trim().is_empty() {
+        if self.current_line.trim_end() != self.prefix().trim_end() {
             self.newline();
         }
     }
 
+    /// Composes segments on a single line, e.g. for a table cell.
+    fn inline(&mut self, segments: &Segments) -> String {
+        let mut composer = Composer::new(self.options.clone());
+        composer.segments(segments);
+        self.footnotes.append(&mut composer.footnotes);
+        composer
+            .lines
+            .into_iter()
+            .chain(std::iter::once(composer.current_line))
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
     fn add(&mut self, s: &str) {
         self.current_line += s;
     }
@@ -48,6 +111,19 @@ impl Composer {
         self.add(" ");
     }
 
+    /// Adds a space if `s` would otherwise continue a control word such as `\alpha`.
+    fn separate_control_word(&mut self, s: &str) {
+        let trimmed = self
+            .current_line
+            .trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        if trimmed.len() < self.current_line.len()
+            && trimmed.ends_with('\\')
+            && s.starts_with(|c: char| c.is_ascii_alphabetic())
+        {
+            self.space();
+        }
+    }
+
     //
 
     fn paragraph(&mut self, paragraph: &Paragraph) {
@@ -72,18 +148,36 @@ impl Composer {
             Segment::Heading(heading) => self.heading(heading),
             Segment::Text(text) => self.text(text),
             Segment::CodeInline(code) => self.code_inline(code),
+            Segment::CodeBlock(code) => self.code_block(code),
             Segment::Strong(strong) => self.strong(strong),
             Segment::Emph(emph) => self.emph(emph),
             Segment::MathInline(math_inline) => self.math_inline(math_inline),
             Segment::MathDisplay(math_display) => self.math_display(math_display),
             Segment::ListItem(list) => self.list_item(list),
+            Segment::TermItem(term) => self.term_item(term),
             Segment::MathDelimited(math_delimited) => self.math_delimited(math_delimited),
+            Segment::MathMiddle(content) => self.math_middle(content),
             Segment::MathAttach(math_attach) => self.math_attach(math_attach),
+            Segment::MathEnv(math_env) => self.math_env(math_env),
+            Segment::MathText(text) => self.math_text(text),
             Segment::MathAlignPoint => self.math_align_point(),
             Segment::Command(command) => self.command(command),
             Segment::RawCommand(command) => self.raw_command(command),
             Segment::Env(env) => self.env(env),
+            Segment::Link(link) => self.link(link),
+            Segment::Footnote(footnote) => self.footnote(footnote),
+            Segment::Quote(quote) => self.quote(quote),
+            Segment::Table(table) => self.table(table),
+            Segment::Image(image) => self.image(image),
+            Segment::Figure(figure) => self.figure(figure),
+            Segment::Label(_) => {}
+            Segment::Ref(ref_) => self.ref_(ref_),
             Segment::ExportComment(comment) => self.export_comment(comment),
+            Segment::InlineComment(comment) => self.add(&format!("<!-- {} -->", comment)),
+            Segment::MathPlaceholder(source) => self.add(&format!(
+                "\\text{{⟨unsupported: {}⟩}}",
+                escape_text_mode(source)
+            )),
         }
     }
 
@@ -94,11 +188,34 @@ impl Composer {
     }
 
     fn text(&mut self, text: &Text) {
+        self.separate_control_word(&text.0);
         self.add(&text.0);
     }
 
     fn code_inline(&mut self, code: &CodeInline) {
-        self.add(&code.0);
+        let fence = code_fence(&code.0, 1);
+        // a space keeps a backtick at either end from merging into the fence
+        let padding = if code.0.starts_with('`') || code.0.ends_with('`') {
+            " "
+        } else {
+            ""
+        };
+        self.add(&format!("{0}{1}{2}{1}{0}", fence, padding, code.0));
+    }
+
+    fn code_block(&mut self, code: &CodeBlock) {
+        let fence = code_fence(&code.code, 3);
+        self.newline_if_not_empty();
+        self.add(&fence);
+        if let Some(lang) = &code.lang {
+            self.add(lang);
+        }
+        for line in code.code.lines() {
+            self.newline();
+            self.add(line);
+        }
+        self.newline();
+        self.add(&fence);
     }
 
     fn strong(&mut self, strong: &Strong) {
@@ -119,31 +236,80 @@ impl Composer {
         self.add("$");
     }
 
+    /// `align*` for alignment points, `gather*` for lines, and `$$` or `equation*` otherwise.
+    /// Numbers are given by `\tag` to agree with references.
     fn math_display(&mut self, math_display: &MathDisplay) {
+        let segments = &math_display.content.0;
+        let env = if segments
+            .iter()
+            .any(|s| matches!(s, Segment::MathAlignPoint))
+        {
+            Some("align*")
+        } else if segments.iter().any(|s| matches!(s, Segment::Linebreak)) {
+            Some("gather*")
+        } else if math_display.number.is_some() {
+            Some("equation*")
+        } else {
+            None
+        };
         self.newline_if_not_empty();
-        self.add("\\begin{align*}");
+        match env {
+            Some(env) => self.add(&format!("\\begin{{{}}}", env)),
+            None => self.add("$$"),
+        }
         self.newline();
         self.segments(&math_display.content);
+        if let Some(number) = &math_display.number {
+            // `\tag` adds parentheses by itself
+            match number.strip_prefix('(').and_then(|n| n.strip_suffix(')')) {
+                Some(number) => self.add(&format!("\\tag{{{}}}", number)),
+                None => self.add(&format!("\\tag*{{{}}}", number)),
+            }
+        }
         self.newline();
-        self.add("\\end{align*}");
+        match env {
+            Some(env) => self.add(&format!("\\end{{{}}}", env)),
+            None => self.add("$$"),
+        }
     }
 
     fn list_item(&mut self, list: &ListItem) {
-        let symbol = match list.symbol {
+        let symbol = match &list.symbol {
             ListSymbol::NoNum => "-".to_string(),
             ListSymbol::NumDot(i) => format!("{}.", i),
             ListSymbol::NumParen(i) => format!("({})", i),
             ListSymbol::NumBrak(i) => format!("[{}]", i),
-            ListSymbol::RomanDot(i) => format!("{}. ", roman(i)),
-            ListSymbol::RomanParen(i) => format!("({}) ", roman(i)),
-            ListSymbol::RomanBrak(i) => format!("[{}] ", roman(i)),
+            ListSymbol::RomanDot(i) => format!("{}. ", roman(*i)),
+            ListSymbol::RomanParen(i) => format!("({}) ", roman(*i)),
+            ListSymbol::RomanBrak(i) => format!("[{}] ", roman(*i)),
+            ListSymbol::RomanLowerDot(i) => format!("{}.", roman(*i).to_lowercase()),
+            ListSymbol::RomanLowerParen(i) => format!("({})", roman(*i).to_lowercase()),
+            ListSymbol::RomanLowerBrak(i) => format!("[{}]", roman(*i).to_lowercase()),
+            ListSymbol::Custom(s) => s.clone(),
         };
         self.newline_if_not_empty();
         self.add(&symbol);
         self.space();
+        self.list_contents(&list.contents, false);
+    }
+
+    fn term_item(&mut self, term: &TermItem) {
+        self.newline_if_not_empty();
+        match &term.term.0[..] {
+            // already bold
+            [Segment::Strong(strong)] => self.strong(strong),
+            _ => self.strong(&Strong {
+                content: term.term.clone(),
+            }),
+        }
+        self.list_contents(&term.description, true);
+    }
+
+    /// Writes the contents of a list item, indenting every line after the current one.
+    fn list_contents(&mut self, contents: &Paragraphs, starts_on_new_line: bool) {
         self.indent();
-        for (i, paragraph) in list.contents.iter().enumerate() {
-            if i != 0 {
+        for (i, paragraph) in contents.iter().enumerate() {
+            if i != 0 || starts_on_new_line {
                 self.newline();
             }
             self.paragraph(paragraph);
@@ -152,33 +318,114 @@ impl Composer {
     }
 
     fn math_delimited(&mut self, math_delimited: &MathDelimited) {
-        // TODO
         let open = &math_delimited.open;
         let body = &math_delimited.body;
         let close = &math_delimited.close;
-        self.add("\\left");
-        self.segments(open);
+        let size = math_delimited.size;
+        let (left, _, right) = delimiter_commands(size);
+        self.delimiter(left, open, size);
+        self.delimiter_sizes.push(size);
         self.segments(body);
-        self.add("\\right");
-        self.segments(close);
+        self.delimiter_sizes.pop();
+        self.delimiter(right, close, size);
+    }
+
+    fn delimiter(&mut self, command: &str, delimiter: &Segments, size: DelimiterSize) {
+        if !delimiter.is_empty() {
+            self.add(command);
+            self.segments(delimiter);
+        } else if size == DelimiterSize::Auto {
+            // `\left` and `\right` must be paired even if there is no delimiter
+            self.add(command);
+            self.add(".");
+        }
+    }
+
+    fn math_middle(&mut self, content: &Segments) {
+        // outside delimiters, a middle delimiter is just a symbol
+        if let Some(&size) = self.delimiter_sizes.last() {
+            let (_, middle, _) = delimiter_commands(size);
+            self.add(middle);
+        }
+        self.segments(content);
     }
 
     fn math_attach(&mut self, math_attach: &MathAttach) {
         let base = &math_attach.base;
         let bottom = &math_attach.bottom;
         let top = &math_attach.top;
+        if math_attach.top_left.is_some() || math_attach.bottom_left.is_some() {
+            // scripts attached to an empty group precede the base
+            self.add("{}");
+            if let Some(top_left) = &math_attach.top_left {
+                self.add("^{");
+                self.segments(top_left);
+                self.add("}");
+            }
+            if let Some(bottom_left) = &math_attach.bottom_left {
+                self.add("_{");
+                self.segments(bottom_left);
+                self.add("}");
+            }
+        }
         self.segments(base);
-        if let Some(bottom) = bottom {
-            self.add("_");
-            self.add("{");
-            self.segments(bottom);
-            self.add("}");
+        self.add(&"'".repeat(math_attach.primes));
+        match math_attach.limits {
+            Some(true) => self.add("\\limits"),
+            Some(false) => self.add("\\nolimits"),
+            None => {}
+        }
+        let scripts = [("_", bottom), ("^", top)];
+        // the superscript must follow primes directly, or `f''_{i}^{2}` is a double superscript
+        let scripts = if math_attach.primes > 0 {
+            [scripts[1], scripts[0]]
+        } else {
+            scripts
+        };
+        for (mark, script) in scripts {
+            if let Some(script) = script {
+                self.add(mark);
+                self.add("{");
+                self.segments(script);
+                self.add("}");
+            }
+        }
+    }
+
+    fn math_env(&mut self, math_env: &MathEnv) {
+        self.add(&format!("\\begin{{{}}}", math_env.name));
+        if let Some(column_spec) = &math_env.column_spec {
+            self.add(&format!("{{{}}}", column_spec));
+        }
+        for (i, row) in math_env.rows.iter().enumerate() {
+            if i != 0 {
+                self.add(" \\\\ ");
+            }
+            if math_env.hlines.contains(&i) {
+                self.add("\\hline ");
+            }
+            for (j, cell) in row.iter().enumerate() {
+                if j != 0 {
+                    self.add(" & ");
+                }
+                self.segments(cell);
+            }
         }
-        if let Some(top) = top {
-            self.add("^");
-            self.add("{");
-            self.segments(top);
-            self.add("}");
+        self.add(&format!("\\end{{{}}}", math_env.name));
+    }
+
+    fn math_text(&mut self, text: &str) {
+        let is_text = match self.options.math_text {
+            MathTextMode::Auto => text
+                .chars()
+                .any(|c| c.is_whitespace() || !c.is_ascii() || matches!(c, '~' | '^' | '\\')),
+            MathTextMode::Text => true,
+            MathTextMode::Mathrm => false,
+        };
+        if is_text {
+            self.add(&format!("\\text{{{}}}", escape_text_mode(text)));
+        } else {
+            self.add(&format!("\\mathrm{{{}}}", escape_math_mode(text)));
         }
     }
 
@@ -191,20 +438,23 @@ impl Composer {
         self.add(&command.name);
         for arg in &command.args {
             if arg.is_optional {
-                self.add("[");
+                // a `]` inside would end the argument early, as in `\sqrt[\left[a\right]]{x}`
+                let content = self.inline(&arg.content);
+                if content.contains(']') {
+                    self.add(&format!("[{{{}}}]", content));
+                } else {
+                    self.add(&format!("[{}]", content));
+                }
             } else {
                 self.add("{");
-            }
-            self.segments(&arg.content);
-            if arg.is_optional {
-                self.add("]");
-            } else {
+                self.segments(&arg.content);
                 self.add("}");
             }
         }
     }
 
     fn raw_command(&mut self, command: &RawCommand) {
+        self.separate_control_word(&command.0);
         self.add(&command.0);
     }
 
@@ -227,16 +477,216 @@ impl Composer {
         self.add("&&&");
     }
 
+    fn link(&mut self, link: &Link) {
+        match (&link.dest, &link.content) {
+            (LinkDest::Url(url), Some(content)) => {
+                self.add("[");
+                self.segments(content);
+                self.add(&format!("]({})", url));
+            }
+            (LinkDest::Url(url), None) => self.add(&format!("<{}>", url)),
+            // nothing in the output defines an anchor for the label, so the text is left unlinked
+            (LinkDest::Label(_), Some(content)) => self.segments(content),
+            (LinkDest::Label(label), None) => match &link.resolved {
+                Some(resolved) => self.ref_target(resolved, None),
+                None => self.add(&format!("@{}", label)),
+            },
+        }
+    }
+
+    fn footnote(&mut self, footnote: &Footnote) {
+        let number = footnote
+            .number
+            .clone()
+            .expect("footnotes are numbered before composing");
+        self.add(&format!("[^{}]", number));
+        // the slot is taken before the body, whose footnotes come after this one
+        let slot = self.footnotes.len();
+        self.footnotes.push((number, String::new()));
+        self.footnotes[slot].1 = self.inline(&footnote.content);
+    }
+
+    fn quote(&mut self, quote: &Quote) {
+        if quote.block {
+            self.newline_if_not_empty();
+            self.add("> ");
+            self.push_prefix("> ");
+        }
+        if quote.quotes {
+            self.add("“");
+        }
+        for (i, paragraph) in quote.contents.iter().enumerate() {
+            if i != 0 {
+                self.newline();
+                self.newline();
+            }
+            self.paragraph(paragraph);
+        }
+        if quote.quotes {
+            self.add("”");
+        }
+        if let Some(attribution) = &quote.attribution {
+            if quote.block {
+                self.newline();
+                self.newline();
+            } else {
+                self.space();
+            }
+            self.add("— ");
+            self.segments(attribution);
+        }
+        if quote.block {
+            self.pop_prefix();
+        }
+    }
+
+    fn table(&mut self, table: &Table) {
+        fn row(composer: &mut Composer, cells: &[Segments]) -> String {
+            let cells = cells
+                .iter()
+                // `|` would end the cell
+                .map(|cell| format!(" {} ", composer.inline(cell).replace('|', "\\|")))
+                .collect::<Vec<_>>();
+            format!("|{}|", cells.join("|"))
+        }
+
+        self.newline_if_not_empty();
+        let header = row(self, &table.header);
+        self.add(&header);
+        self.newline();
+        let rule = table
+            .align
+            .iter()
+            .map(|align| match align {
+                ColumnAlign::Default => " --- ",
+                ColumnAlign::Left => " :--- ",
+                ColumnAlign::Center => " :---: ",
+                ColumnAlign::Right => " ---: ",
+            })
+            .collect::<Vec<_>>();
+        self.add(&format!("|{}|", rule.join("|")));
+        for cells in &table.body {
+            self.newline();
+            let row = row(self, cells);
+            self.add(&row);
+        }
+    }
+
+    fn image(&mut self, image: &Image) {
+        let alt = image.alt.as_deref().unwrap_or_default();
+        self.add(&format!("![{}]({})", alt, image.path));
+    }
+
+    fn figure(&mut self, figure: &Figure) {
+        self.newline_if_not_empty();
+        for (i, paragraph) in figure.body.iter().enumerate() {
+            if i != 0 {
+                self.newline();
+            }
+            self.paragraph(paragraph);
+        }
+        if let Some(caption) = &figure.caption {
+            self.newline();
+            self.newline();
+            if let Some(number) = &figure.number {
+                self.add(&format!("{} {}: ", figure.kind.supplement(), number));
+            }
+            self.segments(caption);
+        }
+    }
+
+    fn ref_(&mut self, ref_: &Ref) {
+        match &ref_.resolved {
+            Some(resolved) => self.ref_target(resolved, ref_.supplement.as_ref()),
+            None => self.add(&format!("@{}", ref_.target)),
+        }
+    }
+
+    /// The text referring to `resolved`, such as `定理 1`, with `supplement` in place of its own.
+    fn ref_target(&mut self, resolved: &RefTarget, supplement: Option<&Segments>) {
+        if resolved.is_footnote {
+            self.add(&format!("[^{}]", resolved.number));
+            return;
+        }
+        if let Some(supplement) = supplement {
+            self.segments(supplement);
+            self.space();
+        } else if !resolved.supplement.is_empty() {
+            self.add(&resolved.supplement);
+            self.space();
+        }
+        self.add(&resolved.number);
+    }
+
     fn export_comment(&mut self, comment: &String) {
         self.newline_if_not_empty();
         self.add(&format!("<!-- {} -->", comment));
     }
 }
 
+/// The commands put before the left, middle and right delimiters.
+fn delimiter_commands(size: DelimiterSize) -> (&'static str, &'static str, &'static str) {
+    match size {
+        DelimiterSize::Auto => ("\\left", "\\middle", "\\right"),
+        DelimiterSize::Normal => ("", "", ""),
+        DelimiterSize::Big => ("\\bigl", "\\bigm", "\\bigr"),
+        DelimiterSize::BigCap => ("\\Bigl", "\\Bigm", "\\Bigr"),
+        DelimiterSize::Bigg => ("\\biggl", "\\biggm", "\\biggr"),
+        DelimiterSize::BiggCap => ("\\Biggl", "\\Biggm", "\\Biggr"),
+    }
+}
+
+/// Escapes the special characters of LaTeX in text mode such as `\text{...}`.
+fn escape_text_mode(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '~' => escaped += "\\textasciitilde{}",
+            '^' => escaped += "\\textasciicircum{}",
+            '\\' => escaped += "\\textbackslash{}",
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes the special characters of LaTeX in math mode such as `\mathrm{...}`,
+/// where spaces would be ignored.
+fn escape_math_mode(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' ' => escaped += "\\ ",
+            '~' => escaped += "\\sim{}",
+            // braced so that following hex digits are not read as part of the code
+            '^' => escaped += "{\\char\"5E}",
+            '\\' => escaped += "\\backslash{}",
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 impl Syntax {
     pub fn compose(&self) -> String {
-        let mut composer = Composer::new();
-        for (i, paragraph) in self.paragraphs.iter().enumerate() {
+        self.compose_with(ComposeOptions::default())
+    }
+
+    pub fn compose_with(&self, options: ComposeOptions) -> String {
+        // numbers footnotes and references even in syntax built by hand; unresolved labels
+        // are left as `@label` and reported by the conversion
+        let mut syntax = self.clone();
+        let _ = syntax.resolve_refs();
+        let mut composer = Composer::new(options);
+        for (i, paragraph) in syntax.paragraphs.iter().enumerate() {
             if i != 0 {
                 composer.newline();
                 composer.newline();
@@ -244,6 +694,13 @@ impl Syntax {
             composer.paragraph(paragraph);
         }
         composer.newline();
+        if !composer.footnotes.is_empty() {
+            composer.newline();
+            for (number, body) in std::mem::take(&mut composer.footnotes) {
+                composer.add(&format!("[^{}]: {}", number, body));
+                composer.newline();
+            }
+        }
         composer.export()
     }
 }
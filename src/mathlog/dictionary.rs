@@ -26,7 +26,7 @@ impl Dictionary {
         self.modules.insert(mod_name.to_string(), dic);
     }
 
-    pub fn read(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn read(path: impl AsRef<std::path::Path>) -> Result<Self, Box<dyn std::error::Error>> {
         let dic_file = std::fs::read_to_string(path)?;
         serde_json::from_str(&dic_file).map_err(|e| e.into())
     }
@@ -1,7 +1,9 @@
 use super::ast as mathlog;
 use super::*;
+use typst::ast::AstNode;
 use typst::syntax as typst;
 
+use core::cell::RefCell;
 use core::fmt;
 
 //
@@ -14,6 +16,17 @@ pub enum FromTypstErrorKind {
     UnsupportedModule(Vec<String>),
     UnsupportedFuncCall(Vec<String>),
     EnvInSegments(String),
+    MissingArg(String, String),
+    UnknownNamedArg(String, String),
+    InvalidArg(String, String),
+    SpreadArgUnsupported(String),
+    MissingEnvBody(String),
+    InvalidTitle(String),
+    UnsupportedCallee(String),
+    InvalidFieldAccess(String),
+    NotContent(String),
+    UnresolvedLabels(Vec<String>),
+    UnnumberedRef(String),
     NotYetImplemented(String),
 }
 
@@ -40,6 +53,47 @@ impl fmt::Display for FromTypstErrorKind {
                 "it is unsupported for an environment {} to be in a line",
                 s
             ),
+            FromTypstErrorKind::MissingArg(func, arg) => {
+                write!(f, "missing argument `{}` of `{}`", arg, func)
+            }
+            FromTypstErrorKind::UnknownNamedArg(func, arg) => {
+                write!(f, "unknown named argument `{}` of `{}`", arg, func)
+            }
+            FromTypstErrorKind::InvalidArg(func, s) => {
+                write!(f, "invalid argument of `{}`: {}", func, s)
+            }
+            FromTypstErrorKind::SpreadArgUnsupported(func) => {
+                write!(f, "spread arguments are unsupported: `{}`", func)
+            }
+            FromTypstErrorKind::MissingEnvBody(env) => {
+                write!(f, "missing body of the environment `{}`", env)
+            }
+            FromTypstErrorKind::InvalidTitle(env) => {
+                write!(
+                    f,
+                    "the title of the environment `{}` must be a single paragraph",
+                    env
+                )
+            }
+            FromTypstErrorKind::UnsupportedCallee(kind) => {
+                write!(f, "unsupported function to call: {}", kind)
+            }
+            FromTypstErrorKind::InvalidFieldAccess(kind) => {
+                write!(f, "field access on {} is unsupported", kind)
+            }
+            FromTypstErrorKind::NotContent(kind) => {
+                write!(
+                    f,
+                    "expected content, a string or a function call, found {}",
+                    kind
+                )
+            }
+            FromTypstErrorKind::UnresolvedLabels(labels) => {
+                write!(f, "unresolved labels: {}", labels.join(", "))
+            }
+            FromTypstErrorKind::UnnumberedRef(label) => {
+                write!(f, "cannot refer to `{}`, which is not numbered", label)
+            }
             FromTypstErrorKind::NotYetImplemented(s) => {
                 write!(f, "not yet implemented: {}", s)
             }
@@ -50,6 +104,8 @@ impl fmt::Display for FromTypstErrorKind {
 #[derive(Debug)]
 pub struct FromTypstError {
     pub kind: FromTypstErrorKind,
+    /// The span of the offending node, detached if unknown.
+    pub span: typst::Span,
 }
 
 impl fmt::Display for FromTypstError {
@@ -63,49 +119,151 @@ impl std::error::Error for FromTypstError {}
 pub type FromTypstResult<T> = Result<T, FromTypstError>;
 
 impl FromTypstError {
-    pub fn unexpected_node(node_kind: &str) -> Self {
+    fn new(kind: FromTypstErrorKind) -> Self {
         Self {
-            kind: FromTypstErrorKind::UnexpectedNode(node_kind.to_string()),
+            kind,
+            span: typst::Span::detached(),
         }
     }
 
-    pub fn unsupported_node(node_kind: &str) -> Self {
-        Self {
-            kind: FromTypstErrorKind::UnsupportedNode(node_kind.to_string()),
+    /// Attaches `span` unless a more specific one is already attached.
+    pub fn or_span(mut self, span: typst::Span) -> Self {
+        if self.span.is_detached() {
+            self.span = span;
         }
+        self
+    }
+
+    pub fn unexpected_node(node_kind: &str) -> Self {
+        Self::new(FromTypstErrorKind::UnexpectedNode(node_kind.to_string()))
+    }
+
+    pub fn unsupported_node(node_kind: &str) -> Self {
+        Self::new(FromTypstErrorKind::UnsupportedNode(node_kind.to_string()))
     }
 
     pub fn unsupported_ident(path: Vec<String>) -> Self {
-        Self {
-            kind: FromTypstErrorKind::UnsupportedIdent(path),
-        }
+        Self::new(FromTypstErrorKind::UnsupportedIdent(path))
     }
 
     pub fn unsupported_module(path: Vec<String>) -> Self {
-        Self {
-            kind: FromTypstErrorKind::UnsupportedModule(path),
-        }
+        Self::new(FromTypstErrorKind::UnsupportedModule(path))
     }
 
     pub fn unsupported_func_call(path: Vec<String>) -> Self {
-        Self {
-            kind: FromTypstErrorKind::UnsupportedFuncCall(path),
-        }
+        Self::new(FromTypstErrorKind::UnsupportedFuncCall(path))
     }
 
     pub fn env_in_segments(s: String) -> Self {
-        Self {
-            kind: FromTypstErrorKind::EnvInSegments(s),
-        }
+        Self::new(FromTypstErrorKind::EnvInSegments(s))
+    }
+
+    pub fn missing_arg(func: &str, arg: &str) -> Self {
+        Self::new(FromTypstErrorKind::MissingArg(
+            func.to_string(),
+            arg.to_string(),
+        ))
+    }
+
+    pub fn unknown_named_arg(func: &str, arg: &str) -> Self {
+        Self::new(FromTypstErrorKind::UnknownNamedArg(
+            func.to_string(),
+            arg.to_string(),
+        ))
+    }
+
+    pub fn invalid_arg(func: &str, s: String) -> Self {
+        Self::new(FromTypstErrorKind::InvalidArg(func.to_string(), s))
+    }
+
+    pub fn spread_arg_unsupported(func: &str) -> Self {
+        Self::new(FromTypstErrorKind::SpreadArgUnsupported(func.to_string()))
+    }
+
+    pub fn missing_env_body(env: String) -> Self {
+        Self::new(FromTypstErrorKind::MissingEnvBody(env))
+    }
+
+    pub fn invalid_title(env: String) -> Self {
+        Self::new(FromTypstErrorKind::InvalidTitle(env))
+    }
+
+    /// A call of something other than a function name, e.g. `(f)(x)` or `calc.pow(..)`.
+    pub fn unsupported_callee(callee: &typst::ast::Expr) -> Self {
+        let kind = match callee {
+            typst::ast::Expr::FieldAccess(_) => callee.to_untyped().clone().into_text().to_string(),
+            _ => callee.to_untyped().kind().name().to_string(),
+        };
+        Self::new(FromTypstErrorKind::UnsupportedCallee(kind)).or_span(callee.span())
+    }
+
+    pub fn invalid_field_access(target: &typst::ast::Expr) -> Self {
+        let kind = target.to_untyped().kind().name().to_string();
+        Self::new(FromTypstErrorKind::InvalidFieldAccess(kind)).or_span(target.span())
+    }
+
+    pub fn not_content(node: &typst::ast::Expr) -> Self {
+        let kind = node.to_untyped().kind().name().to_string();
+        Self::new(FromTypstErrorKind::NotContent(kind)).or_span(node.span())
+    }
+
+    pub fn unresolved_labels(labels: Vec<String>) -> Self {
+        Self::new(FromTypstErrorKind::UnresolvedLabels(labels))
+    }
+
+    pub fn unnumbered_ref(label: &str) -> Self {
+        Self::new(FromTypstErrorKind::UnnumberedRef(label.to_string()))
     }
 
     pub fn not_yet_implemented(s: String) -> Self {
-        Self {
-            kind: FromTypstErrorKind::NotYetImplemented(s),
+        Self::new(FromTypstErrorKind::NotYetImplemented(s))
+    }
+}
+
+/// A construct converted with a loss, such as an ignored rule.
+#[derive(Debug)]
+pub enum FromTypstWarningKind {
+    ShowRuleIgnored,
+    SetRuleIgnored,
+    SetArgIgnored(String, String),
+    /// An argument which only affects the appearance.
+    ArgIgnored(String, String),
+    /// A label after an element which cannot be referred to.
+    LabelIgnored,
+    SpaceDropped,
+}
+
+impl fmt::Display for FromTypstWarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromTypstWarningKind::ShowRuleIgnored => write!(f, "show rules are ignored"),
+            FromTypstWarningKind::SetRuleIgnored => write!(f, "this set rule is ignored"),
+            FromTypstWarningKind::SetArgIgnored(func, arg) => {
+                write!(
+                    f,
+                    "argument `{}` of set rules for `{}` is ignored",
+                    arg, func
+                )
+            }
+            FromTypstWarningKind::ArgIgnored(func, arg) => {
+                write!(f, "argument `{}` of `{}` is ignored", arg, func)
+            }
+            FromTypstWarningKind::LabelIgnored => {
+                write!(f, "the label is ignored since it cannot be referred to")
+            }
+            FromTypstWarningKind::SpaceDropped => {
+                write!(f, "the space is dropped and the words around it are joined")
+            }
         }
     }
 }
 
+#[derive(Debug)]
+pub struct FromTypstWarning {
+    pub kind: FromTypstWarningKind,
+    pub span: typst::Span,
+}
+
 //
 
 struct SegmentWriter {
@@ -143,9 +301,22 @@ impl Default for SegmentWriter {
     }
 }
 
+/// Styles set by `#set` rules, which apply to the rest of the enclosing content.
+#[derive(Debug, Clone, Default)]
+struct Styles {
+    enum_numbering: Option<String>,
+    equation_numbering: Option<String>,
+    heading_numbering: Option<String>,
+    /// The number of enumerations enclosing the content.
+    enum_depth: usize,
+}
+
 struct ParagraphWriter {
     paragraphs: Vec<mathlog::Paragraph>,
     segments_writer: SegmentWriter,
+    styles: Styles,
+    /// The number of the last enumeration item, to continue numbering the following items.
+    enum_number: Option<usize>,
 }
 
 impl ParagraphWriter {
@@ -153,6 +324,26 @@ impl ParagraphWriter {
         Self {
             paragraphs: Vec::new(),
             segments_writer: SegmentWriter::new(),
+            styles: Styles::default(),
+            enum_number: None,
+        }
+    }
+
+    /// A writer for nested content, which inherits the styles.
+    fn child(&self) -> Self {
+        Self {
+            styles: self.styles.clone(),
+            ..Self::new()
+        }
+    }
+
+    fn last_segment(&self) -> Option<&mathlog::Segment> {
+        if self.segments_writer.is_empty() {
+            self.paragraphs
+                .last()
+                .and_then(|paragraph| paragraph.segments.0.last())
+        } else {
+            self.segments_writer.segments.0.last()
         }
     }
 
@@ -183,49 +374,251 @@ impl ParagraphWriter {
 
 //
 
+/// Options of the conversion from Typst.
+#[derive(Debug, Clone, Default)]
+pub struct FromTypstOptions {
+    /// Whether to go on after an error, leaving the source of each node which fails to convert
+    /// in a comment.
+    pub lenient: bool,
+}
+
+/// The converted syntax, and the errors and warnings met on the way.
+#[derive(Debug)]
+pub struct FromTypstOutput {
+    pub syntax: mathlog::Syntax,
+    /// Errors recorded in lenient mode.
+    pub errors: Vec<FromTypstError>,
+    pub warnings: Vec<FromTypstWarning>,
+}
+
 impl mathlog::Syntax {
     pub fn from_typst(node: &typst::ast::Markup, dic: &Dictionary) -> FromTypstResult<Self> {
+        Self::from_typst_with(node, dic, FromTypstOptions::default()).map(|output| output.syntax)
+    }
+
+    /// Converts `node` collecting warnings, which never fails in lenient mode.
+    pub fn from_typst_with(
+        node: &typst::ast::Markup,
+        dic: &Dictionary,
+        options: FromTypstOptions,
+    ) -> FromTypstResult<FromTypstOutput> {
+        let ctx = Context::new(dic, options.lenient);
+        let syntax = Self::from_typst_in(node, &ctx)?;
+        Ok(FromTypstOutput {
+            syntax,
+            errors: ctx.errors.into_inner(),
+            warnings: ctx.warnings.into_inner(),
+        })
+    }
+
+    fn from_typst_in(node: &typst::ast::Markup, ctx: &Context) -> FromTypstResult<Self> {
         let mut writer = ParagraphWriter::new();
-        writer.markup(node, dic)?;
+        writer.markup(node, ctx)?;
         let paragraphs = writer.export();
-        Ok(Self { paragraphs })
+        let mut syntax = Self { paragraphs };
+        if let Err(unresolved) = syntax.resolve_refs() {
+            let span_of = |label: &String| {
+                ref_span(node.to_untyped(), label).unwrap_or_else(typst::Span::detached)
+            };
+            if let Some(label) = unresolved.missing.first() {
+                let span = span_of(label);
+                ctx.record(FromTypstError::unresolved_labels(unresolved.missing).or_span(span))?;
+            }
+            for label in &unresolved.unnumbered {
+                ctx.record(FromTypstError::unnumbered_ref(label).or_span(span_of(label)))?;
+            }
+        }
+        Ok(syntax)
+    }
+}
+
+/// The state shared by the writers through a conversion.
+struct Context<'a> {
+    dic: &'a Dictionary,
+    /// Whether to record errors and go on instead of failing.
+    lenient: bool,
+    errors: RefCell<Vec<FromTypstError>>,
+    warnings: RefCell<Vec<FromTypstWarning>>,
+}
+
+impl<'a> Context<'a> {
+    fn new(dic: &'a Dictionary, lenient: bool) -> Self {
+        Self {
+            dic,
+            lenient,
+            errors: RefCell::new(Vec::new()),
+            warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn warn(&self, kind: FromTypstWarningKind, span: typst::Span) {
+        self.warnings
+            .borrow_mut()
+            .push(FromTypstWarning { kind, span });
+    }
+
+    /// Fails with `err`, or records it in lenient mode.
+    fn record(&self, err: FromTypstError) -> FromTypstResult<()> {
+        if !self.lenient {
+            return Err(err);
+        }
+        self.errors.borrow_mut().push(err);
+        Ok(())
+    }
+}
+
+/// Whether `exprs[i]` is a space between two words in ASCII, which are joined by dropping it.
+/// Spaces next to other characters, such as Japanese, are dropped without a loss.
+fn joins_words(exprs: &[typst::ast::Expr], i: usize) -> bool {
+    let text = |expr: &typst::ast::Expr| match expr {
+        // the words are in the body, not in the markers `*` and `_`
+        typst::ast::Expr::Strong(strong) => Some(strong.body().to_untyped().clone().into_text()),
+        typst::ast::Expr::Emph(emph) => Some(emph.body().to_untyped().clone().into_text()),
+        typst::ast::Expr::Text(_)
+        | typst::ast::Expr::Raw(_)
+        | typst::ast::Expr::Link(_)
+        | typst::ast::Expr::Ref(_)
+        | typst::ast::Expr::Escape(_)
+        | typst::ast::Expr::Shorthand(_)
+        | typst::ast::Expr::SmartQuote(_) => Some(expr.to_untyped().clone().into_text()),
+        typst::ast::Expr::Equation(equation) if !equation.block() => {
+            Some(expr.to_untyped().clone().into_text())
+        }
+        _ => None,
+    };
+    let (Some(typst::ast::Expr::Space(_)), Some(prev), Some(next)) = (
+        exprs.get(i),
+        i.checked_sub(1).and_then(|i| exprs.get(i)),
+        exprs.get(i + 1),
+    ) else {
+        return false;
+    };
+    let last = text(prev).and_then(|text| text.chars().last());
+    let first = text(next).and_then(|text| text.chars().next());
+    matches!((last, first), (Some(last), Some(first)) if last.is_ascii_graphic() && first.is_ascii_graphic())
+}
+
+/// The segment left in place of a node which failed to convert in lenient mode, a comment on
+/// its own line for a block and within the line otherwise.
+fn placeholder(node: &typst::ast::Expr, block: bool) -> mathlog::Segment {
+    // `--` would end the comment early
+    let text = source_text(node).replace("--", "- -");
+    if block {
+        mathlog::Segment::ExportComment(text)
+    } else {
+        mathlog::Segment::InlineComment(text)
+    }
+}
+
+/// The placeholder in math, where a comment would break the equation.
+fn math_placeholder(node: &typst::ast::Expr) -> mathlog::Segment {
+    mathlog::Segment::MathPlaceholder(source_text(node))
+}
+
+/// Whether `node` in markup is converted into a block rather than a part of a paragraph.
+fn is_block(node: &typst::ast::Expr) -> bool {
+    match node {
+        typst::ast::Expr::Heading(_)
+        | typst::ast::Expr::List(_)
+        | typst::ast::Expr::Enum(_)
+        | typst::ast::Expr::Term(_) => true,
+        typst::ast::Expr::Equation(equation) => equation.block(),
+        typst::ast::Expr::Raw(raw) => raw.block(),
+        typst::ast::Expr::FuncCall(call) => {
+            let typst::ast::Expr::Ident(ident) = call.callee() else {
+                return false;
+            };
+            match ident.get().as_str() {
+                "table" | "grid" | "figure" | "enum" => true,
+                "quote" => call.args().items().any(|arg| {
+                    matches!(arg, typst::ast::Arg::Named(named)
+                        if named.name().get() == "block"
+                            && matches!(named.expr(), typst::ast::Expr::Bool(b) if b.get()))
+                }),
+                name => mathlog::EnvKind::from_name(name).is_some(),
+            }
+        }
+        _ => false,
+    }
+}
+
+fn source_text(node: &typst::ast::Expr) -> String {
+    let text = node.to_untyped().clone().into_text();
+    // the node in markup leaves out the `#` before it
+    if node.hash() {
+        format!("#{}", text)
+    } else {
+        text.to_string()
     }
 }
 
+/// The span of the first reference to `label` in `node`, `@label`, `#footnote(<label>)` or
+/// `#link(<label>)`.
+fn ref_span(node: &typst::SyntaxNode, label: &str) -> Option<typst::Span> {
+    if let Some(reference) = node.cast::<typst::ast::Ref>() {
+        if reference.target() == label {
+            return Some(node.span());
+        }
+    }
+    if let Some(call) = node.cast::<typst::ast::FuncCall>() {
+        let takes_label = matches!(call.callee(), typst::ast::Expr::Ident(ident)
+            if ident.get() == "footnote" || ident.get() == "link");
+        let refers = call.args().items().any(|arg| {
+            matches!(arg, typst::ast::Arg::Pos(typst::ast::Expr::Label(target)) if target.get() == label)
+        });
+        if takes_label && refers {
+            return Some(node.span());
+        }
+    }
+    node.children().find_map(|child| ref_span(child, label))
+}
+
 //
 
 impl ParagraphWriter {
-    fn markup(&mut self, node: &typst::ast::Markup, dic: &Dictionary) -> FromTypstResult<()> {
-        for node in node.exprs() {
-            self.expr(&node, dic)?;
+    fn markup(&mut self, node: &typst::ast::Markup, ctx: &Context) -> FromTypstResult<()> {
+        let exprs = node.exprs().collect::<Vec<_>>();
+        for (i, node) in exprs.iter().enumerate() {
+            if joins_words(&exprs, i) {
+                ctx.warn(FromTypstWarningKind::SpaceDropped, node.span());
+            }
+            let len = (self.paragraphs.len(), self.segments_writer.segments.0.len());
+            if let Err(err) = self.expr(node, ctx) {
+                ctx.record(err)?;
+                // drop what the node wrote before failing, unless it has closed the paragraph
+                if self.paragraphs.len() == len.0 {
+                    self.segments_writer.segments.0.truncate(len.1);
+                }
+                self.push_segment(placeholder(node, is_block(node)));
+            }
         }
         Ok(())
     }
 
-    fn expr(&mut self, node: &typst::ast::Expr, dic: &Dictionary) -> FromTypstResult<()> {
-        match node {
+    fn expr(&mut self, node: &typst::ast::Expr, ctx: &Context) -> FromTypstResult<()> {
+        let result = match node {
             typst::ast::Expr::Parbreak(parbreak) => self.parbreak(parbreak),
-            typst::ast::Expr::Heading(heading) => self.heading(heading, dic),
+            typst::ast::Expr::Heading(heading) => self.heading(heading, ctx),
             typst::ast::Expr::Space(space) => self.segments_writer.space(space),
             typst::ast::Expr::Text(text) => self.segments_writer.text(text),
-            typst::ast::Expr::Strong(strong) => self.segments_writer.strong(strong, dic),
-            typst::ast::Expr::Emph(emph) => self.segments_writer.emph(emph, dic),
-            typst::ast::Expr::Equation(equation) => self.equation(equation, dic),
-            typst::ast::Expr::List(list) => self.list_item(list, dic),
-            typst::ast::Expr::Enum(enum_item) => self.enum_item(enum_item, dic),
-            typst::ast::Expr::Show(show) => self.show(show),
-            typst::ast::Expr::FuncCall(func_call) => self.func_call(func_call, dic),
+            typst::ast::Expr::Strong(strong) => self.segments_writer.strong(strong, ctx),
+            typst::ast::Expr::Emph(emph) => self.segments_writer.emph(emph, ctx),
+            typst::ast::Expr::Equation(equation) => self.equation(equation, ctx),
+            typst::ast::Expr::List(list) => self.list_item(list, ctx),
+            typst::ast::Expr::Enum(enum_item) => self.enum_item(enum_item, ctx),
+            typst::ast::Expr::Show(show) => self.segments_writer.show(show, ctx),
+            typst::ast::Expr::FuncCall(func_call) => self.func_call(func_call, ctx),
             typst::ast::Expr::Import(import) => self.import(import),
             typst::ast::Expr::Include(include) => self.include(include),
             typst::ast::Expr::Linebreak(linebreak) => self.segments_writer.linebreak(linebreak),
             typst::ast::Expr::Escape(escape) => self.segments_writer.escape(escape),
             typst::ast::Expr::Shorthand(shorthand) => self.segments_writer.shorthand(shorthand),
             typst::ast::Expr::SmartQuote(quote) => self.segments_writer.smart_quote(quote),
-            typst::ast::Expr::Raw(raw) => self.segments_writer.raw(raw),
-            typst::ast::Expr::Link(link) => self.link(link),
-            typst::ast::Expr::Label(label) => self.label(label),
-            typst::ast::Expr::Ref(ref_) => self.ref_(ref_),
-            typst::ast::Expr::Term(term) => self.term(term),
+            typst::ast::Expr::Raw(raw) => self.raw(raw),
+            typst::ast::Expr::Link(link) => self.segments_writer.link(link),
+            typst::ast::Expr::Label(label) => self.label(label, ctx),
+            typst::ast::Expr::Ref(ref_) => self.segments_writer.ref_(ref_, ctx),
+            typst::ast::Expr::Term(term) => self.term(term, ctx),
             //
             typst::ast::Expr::Math(_) => Err(FromTypstError::unexpected_node("math")),
             typst::ast::Expr::MathIdent(_) => Err(FromTypstError::unexpected_node("math ident")),
@@ -263,7 +656,7 @@ impl ParagraphWriter {
             typst::ast::Expr::DestructAssign(_) => {
                 Err(FromTypstError::unexpected_node("destruct assign"))
             }
-            typst::ast::Expr::Set(_) => Err(FromTypstError::unsupported_node("set")),
+            typst::ast::Expr::Set(set) => self.set(set, ctx),
             typst::ast::Expr::Conditional(_) => Err(FromTypstError::unexpected_node("conditional")),
             typst::ast::Expr::While(_) => Err(FromTypstError::unexpected_node("while")),
             typst::ast::Expr::For(_) => Err(FromTypstError::unexpected_node("for")),
@@ -272,7 +665,8 @@ impl ParagraphWriter {
             typst::ast::Expr::Return(_) => Err(FromTypstError::unexpected_node("return")),
             typst::ast::Expr::MathPrimes(_) => Err(FromTypstError::unexpected_node("math primes")),
             typst::ast::Expr::Contextual(_) => Err(FromTypstError::unexpected_node("contextual")),
-        }
+        };
+        result.map_err(|err| err.or_span(node.span()))
     }
 
     fn parbreak(&mut self, _node: &typst::ast::Parbreak) -> FromTypstResult<()> {
@@ -280,28 +674,41 @@ impl ParagraphWriter {
         Ok(())
     }
 
-    fn heading(&mut self, node: &typst::ast::Heading, dic: &Dictionary) -> FromTypstResult<()> {
+    fn heading(&mut self, node: &typst::ast::Heading, ctx: &Context) -> FromTypstResult<()> {
         let depth = node.depth().get();
         let body = node.body();
         let mut writer = SegmentWriter::new();
-        writer.markup(&body, dic)?;
-        let content = writer.export();
+        writer.markup(&body, ctx)?;
+        let mut content = writer.export();
+        // `= Heading <label>` puts the label at the end of the heading body
+        let label = match content.0.last() {
+            Some(mathlog::Segment::Label(_)) => match content.0.pop() {
+                Some(mathlog::Segment::Label(label)) => Some(label),
+                _ => unreachable!(),
+            },
+            _ => None,
+        };
         self.push_segment(mathlog::Segment::Heading(mathlog::Heading {
             depth,
             content,
+            numbered: self.styles.heading_numbering.is_some(),
+            label,
         }));
         self.push_paragraph();
         Ok(())
     }
 
-    fn equation(&mut self, node: &typst::ast::Equation, dic: &Dictionary) -> FromTypstResult<()> {
+    fn equation(&mut self, node: &typst::ast::Equation, ctx: &Context) -> FromTypstResult<()> {
         let mut writer = SegmentWriter::new();
-        writer.math(&node.body(), dic)?;
+        writer.math(&node.body(), ctx)?;
         let content = writer.export();
         if node.block() {
             self.push_paragraph_if_not_empty();
             self.push_segment(mathlog::Segment::MathDisplay(mathlog::MathDisplay {
                 content,
+                numbering: self.styles.equation_numbering.clone(),
+                label: None,
+                number: None,
             }));
             self.push_paragraph();
         } else {
@@ -312,10 +719,10 @@ impl ParagraphWriter {
         Ok(())
     }
 
-    fn list_item(&mut self, node: &typst::ast::ListItem, dic: &Dictionary) -> FromTypstResult<()> {
+    fn list_item(&mut self, node: &typst::ast::ListItem, ctx: &Context) -> FromTypstResult<()> {
+        let mut writer = self.child();
+        writer.markup(&node.body(), ctx)?;
         self.push_paragraph_if_not_empty();
-        let mut writer = ParagraphWriter::new();
-        writer.markup(&node.body(), dic)?;
         self.push_segment(mathlog::Segment::ListItem(mathlog::ListItem {
             symbol: mathlog::ListSymbol::NoNum,
             contents: writer.export(),
@@ -323,38 +730,211 @@ impl ParagraphWriter {
         Ok(())
     }
 
-    fn enum_item(&mut self, node: &typst::ast::EnumItem, dic: &Dictionary) -> FromTypstResult<()> {
-        let symbol = mathlog::ListSymbol::NumDot(node.number().unwrap_or(1));
+    fn enum_item(&mut self, node: &typst::ast::EnumItem, ctx: &Context) -> FromTypstResult<()> {
+        // items following another item continue its numbering unless numbered explicitly
+        let continues = matches!(
+            self.last_segment(),
+            Some(mathlog::Segment::ListItem(mathlog::ListItem { symbol, .. }))
+                if !matches!(symbol, mathlog::ListSymbol::NoNum)
+        );
+        let number = match (node.number(), self.enum_number) {
+            (Some(number), _) => number,
+            (None, Some(last)) if continues => last + 1,
+            (None, _) => 1,
+        };
+        let numbering = self.styles.enum_numbering.clone();
+        let mut writer = self.child();
+        writer.styles.enum_depth += 1;
+        writer.markup(&node.body(), ctx)?;
+        let symbol = self.enum_symbol(numbering.as_deref(), number)?;
+        self.push_enum_item(symbol, number, writer.export());
+        Ok(())
+    }
+
+    fn enum_symbol(
+        &self,
+        numbering: Option<&str>,
+        number: usize,
+    ) -> FromTypstResult<mathlog::ListSymbol> {
+        let symbol = match numbering {
+            Some(pattern) => {
+                mathlog::ListSymbol::from_numbering(pattern, self.styles.enum_depth, number)
+                    .ok_or_else(|| {
+                        FromTypstError::invalid_arg(
+                            "enum",
+                            format!("invalid numbering pattern \"{}\"", pattern),
+                        )
+                    })?
+            }
+            None => mathlog::ListSymbol::NumDot(number),
+        };
+        Ok(symbol)
+    }
+
+    fn push_enum_item(
+        &mut self,
+        symbol: mathlog::ListSymbol,
+        number: usize,
+        contents: Vec<mathlog::Paragraph>,
+    ) {
         self.push_paragraph_if_not_empty();
-        let mut writer = ParagraphWriter::new();
-        writer.markup(&node.body(), dic)?;
         self.push_segment(mathlog::Segment::ListItem(mathlog::ListItem {
             symbol,
-            contents: writer.export(),
+            contents,
         }));
-        Ok(())
+        self.enum_number = Some(number);
     }
 
-    fn show(&mut self, _node: &typst::ast::ShowRule) -> FromTypstResult<()> {
-        // ignore but leave an export comment
-        // self.push_paragraph_if_not_empty();
-        self.segments_writer
-            .push_segment(mathlog::Segment::ExportComment("#show".to_string()));
+    /// `#enum(..)` with items given as arguments.
+    fn enum_call(&mut self, node: &typst::ast::FuncCall, ctx: &Context) -> FromTypstResult<()> {
+        let mut numbering = self.styles.enum_numbering.clone();
+        let mut number = 1;
+        let mut items = vec![];
+        for arg in node.args().items() {
+            match arg {
+                typst::ast::Arg::Pos(expr) => items.push(expr),
+                typst::ast::Arg::Named(named) => match named.name().get().as_str() {
+                    "numbering" => numbering = Some(numbering_arg("enum", &named)?),
+                    "start" => match named.expr() {
+                        typst::ast::Expr::Int(int) if int.get() >= 0 => {
+                            number = int.get() as usize;
+                        }
+                        _ => {
+                            return Err(FromTypstError::invalid_arg(
+                                "enum",
+                                "`start` must be a non-negative integer".to_string(),
+                            )
+                            .or_span(named.expr().span()))
+                        }
+                    },
+                    _ if ignore_appearance_arg("enum", &named, ctx) => {}
+                    name => {
+                        return Err(
+                            FromTypstError::unknown_named_arg("enum", name).or_span(named.span())
+                        )
+                    }
+                },
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(
+                        FromTypstError::spread_arg_unsupported("enum").or_span(spread.span())
+                    )
+                }
+            }
+        }
+        // every item is converted before any is pushed, so that a failure leaves nothing behind
+        let mut converted = Vec::new();
+        for item in items {
+            let mut writer = self.child();
+            writer.styles.enum_depth += 1;
+            writer.eval_expr(&item, ctx)?;
+            let symbol = self.enum_symbol(numbering.as_deref(), number)?;
+            converted.push((symbol, number, writer.export()));
+            number += 1;
+        }
+        for (symbol, number, contents) in converted {
+            self.push_enum_item(symbol, number, contents);
+        }
+        // a separate enumeration from items in markup
+        self.enum_number = None;
         Ok(())
     }
 
-    fn func_call(&mut self, node: &typst::ast::FuncCall, dic: &Dictionary) -> FromTypstResult<()> {
+    fn set(&mut self, node: &typst::ast::SetRule, ctx: &Context) -> FromTypstResult<()> {
+        let ignore_arg = |func: &str, arg: typst::ast::Arg| {
+            let name = match arg {
+                typst::ast::Arg::Named(named) => named.name().get().to_string(),
+                _ => "..".to_string(),
+            };
+            ctx.warn(
+                FromTypstWarningKind::SetArgIgnored(func.to_string(), name),
+                arg.span(),
+            );
+        };
+        match node.target() {
+            typst::ast::Expr::Ident(ident) if ident.get() == "enum" => {
+                for arg in node.args().items() {
+                    match arg {
+                        typst::ast::Arg::Named(named) if named.name().get() == "numbering" => {
+                            self.styles.enum_numbering = Some(numbering_arg("enum", &named)?);
+                        }
+                        _ => ignore_arg("enum", arg),
+                    }
+                }
+                Ok(())
+            }
+            typst::ast::Expr::Ident(ident) if ident.get() == "heading" => {
+                for arg in node.args().items() {
+                    match arg {
+                        typst::ast::Arg::Named(named) if named.name().get() == "numbering" => {
+                            self.styles.heading_numbering = match named.expr() {
+                                typst::ast::Expr::None(_) => None,
+                                _ => Some(numbering_arg("heading", &named)?),
+                            };
+                        }
+                        _ => ignore_arg("heading", arg),
+                    }
+                }
+                Ok(())
+            }
+            typst::ast::Expr::FieldAccess(access)
+                if matches!(access.target(), typst::ast::Expr::Ident(ident) if ident.get() == "math")
+                    && access.field().get() == "equation" =>
+            {
+                for arg in node.args().items() {
+                    match arg {
+                        typst::ast::Arg::Named(named) if named.name().get() == "numbering" => {
+                            self.styles.equation_numbering = match named.expr() {
+                                typst::ast::Expr::None(_) => None,
+                                _ => Some(numbering_arg("math.equation", &named)?),
+                            };
+                        }
+                        _ => ignore_arg("math.equation", arg),
+                    }
+                }
+                Ok(())
+            }
+            _ => {
+                ctx.warn(FromTypstWarningKind::SetRuleIgnored, node.span());
+                Ok(())
+            }
+        }
+    }
+
+    fn raw(&mut self, node: &typst::ast::Raw) -> FromTypstResult<()> {
+        if node.block() {
+            self.push_paragraph_if_not_empty();
+            self.segments_writer.raw(node)?;
+            self.push_paragraph();
+            Ok(())
+        } else {
+            self.segments_writer.raw(node)
+        }
+    }
+
+    fn func_call(&mut self, node: &typst::ast::FuncCall, ctx: &Context) -> FromTypstResult<()> {
         // TODO: add special cases, for example, for `#strong`
         match node.callee() {
             typst::ast::Expr::Ident(ident) => {
                 let ident = ident.get();
                 if let Some(kind) = mathlog::EnvKind::from_name(ident) {
-                    self.env(node, dic, kind)
+                    self.env(node, ctx, kind)
+                } else if ident == "table" || ident == "grid" {
+                    self.table(node, ctx, ident)
+                } else if ident == "figure" {
+                    self.figure(node, ctx)
+                } else if ident == "quote" {
+                    self.quote(node, ctx)
+                } else if ident == "enum" {
+                    self.enum_call(node, ctx)
+                } else if SegmentWriter::INLINE_FUNCS.contains(&ident.as_str()) {
+                    self.segments_writer.func_call(node, ctx)
                 } else {
-                    todo!("unimplemented function: {:?}", node)
+                    Err(FromTypstError::unsupported_func_call(vec![
+                        ident.to_string()
+                    ]))
                 }
             }
-            _ => todo!("{:?}", node),
+            callee => Err(FromTypstError::unsupported_callee(&callee)),
         }
     }
 
@@ -415,24 +995,39 @@ impl ParagraphWriter {
         Ok(())
     }
 
-    fn link(&self, _link: &typst::ast::Link) -> Result<(), FromTypstError> {
-        // TODO: implement
-        Err(FromTypstError::not_yet_implemented("link".to_string()))
-    }
-
-    fn label(&self, _label: &typst::ast::Label) -> Result<(), FromTypstError> {
-        // TODO: implement
-        Err(FromTypstError::not_yet_implemented("label".to_string()))
-    }
-
-    fn ref_(&self, _ref_: &typst::ast::Ref) -> Result<(), FromTypstError> {
-        // TODO: implement
-        Err(FromTypstError::not_yet_implemented("ref".to_string()))
+    fn label(&mut self, node: &typst::ast::Label, ctx: &Context) -> FromTypstResult<()> {
+        // a label attaches to the preceding heading, environment or display equation,
+        // all of which close the paragraph they are in
+        let label = node.get().to_string();
+        // labels of inline elements such as footnotes are handled by `SegmentWriter`
+        if self.segments_writer.is_empty() {
+            let slot = self
+                .paragraphs
+                .last_mut()
+                .and_then(|paragraph| paragraph.segments.0.last_mut())
+                .and_then(|segment| segment.label_mut());
+            if let Some(slot @ None) = slot {
+                *slot = Some(label);
+                return Ok(());
+            }
+            // e.g. after a block quote; kept as a segment, it would make a paragraph of its own
+            ctx.warn(FromTypstWarningKind::LabelIgnored, node.span());
+            return Ok(());
+        }
+        self.segments_writer.label(node)
     }
 
-    fn term(&self, _term: &typst::ast::TermItem) -> Result<(), FromTypstError> {
-        // TODO: implement
-        Err(FromTypstError::not_yet_implemented("term".to_string()))
+    fn term(&mut self, node: &typst::ast::TermItem, ctx: &Context) -> FromTypstResult<()> {
+        let mut term_writer = SegmentWriter::new();
+        term_writer.markup(&node.term(), ctx)?;
+        let mut description_writer = self.child();
+        description_writer.markup(&node.description(), ctx)?;
+        self.push_paragraph_if_not_empty();
+        self.push_segment(mathlog::Segment::TermItem(mathlog::TermItem {
+            term: term_writer.export(),
+            description: description_writer.export(),
+        }));
+        Ok(())
     }
 
     //
@@ -440,7 +1035,7 @@ impl ParagraphWriter {
     fn env(
         &mut self,
         node: &typst::ast::FuncCall,
-        dic: &Dictionary,
+        ctx: &Context,
         kind: mathlog::EnvKind,
     ) -> FromTypstResult<()> {
         let args = node.args();
@@ -449,8 +1044,8 @@ impl ParagraphWriter {
         for arg in args.items() {
             match arg {
                 typst::ast::Arg::Pos(body_expr) => {
-                    let mut writer = ParagraphWriter::new();
-                    writer.eval_expr(&body_expr, dic)?;
+                    let mut writer = self.child();
+                    writer.eval_expr(&body_expr, ctx)?;
                     let paragraphs = writer.export();
                     body = Some(paragraphs);
                 }
@@ -462,103 +1057,353 @@ impl ParagraphWriter {
                     let ident = named.name();
                     match &*ident.get().to_string() {
                         "body" => {
-                            let mut writer = ParagraphWriter::new();
-                            writer.eval_expr(&named.expr(), dic)?;
+                            let mut writer = self.child();
+                            writer.eval_expr(&named.expr(), ctx)?;
                             body = Some(writer.export());
                         }
                         "title" => {
-                            let mut writer = ParagraphWriter::new();
-                            writer.eval_expr(&named.expr(), dic)?;
+                            let mut writer = self.child();
+                            writer.eval_expr(&named.expr(), ctx)?;
                             let paragraphs = writer.export();
-                            if paragraphs.len() != 1 {
-                                todo!(); //TODO: handle error
-                            }
-                            title = Some(paragraphs[0].segments.clone());
+                            let [paragraph] = &paragraphs[..] else {
+                                return Err(FromTypstError::invalid_title(kind.typst_name())
+                                    .or_span(named.expr().span()));
+                            };
+                            title = Some(paragraph.segments.clone());
+                        }
+                        name => {
+                            return Err(FromTypstError::unknown_named_arg(&kind.typst_name(), name)
+                                .or_span(named.span()))
                         }
-                        _ => todo!(), //TODO: handle error
                     }
                 }
-                typst::ast::Arg::Spread(_spread) => {
-                    todo!() // TODO: handle error
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(FromTypstError::spread_arg_unsupported(&kind.typst_name())
+                        .or_span(spread.span()))
                 }
             }
         }
         let Some(contents) = body else {
-            todo!(); // TODO: handle error
+            return Err(FromTypstError::missing_env_body(kind.typst_name()));
         };
 
         self.push_segment(mathlog::Segment::Env(mathlog::Env {
             kind,
             title,
             contents,
+            label: None,
         }));
         self.push_paragraph();
         Ok(())
     }
-}
 
-impl SegmentWriter {
-    fn markup(&mut self, node: &typst::ast::Markup, dic: &Dictionary) -> FromTypstResult<()> {
-        for node in node.exprs() {
-            self.expr(&node, dic)?;
+    fn quote(&mut self, node: &typst::ast::FuncCall, ctx: &Context) -> FromTypstResult<()> {
+        let args = QuoteArgs::new(&node.args(), ctx)?;
+        if !args.block {
+            return self.segments_writer.inline_quote(args, ctx);
         }
+        let mut writer = self.child();
+        writer.eval_expr(&args.body, ctx)?;
+        self.push_paragraph_if_not_empty();
+        self.push_segment(mathlog::Segment::Quote(mathlog::Quote {
+            block: true,
+            quotes: args.quotes.unwrap_or(false),
+            contents: writer.export(),
+            attribution: args.attribution,
+        }));
+        self.push_paragraph();
         Ok(())
     }
 
-    fn expr(&mut self, node: &typst::ast::Expr, dic: &Dictionary) -> FromTypstResult<()> {
-        match node {
-            typst::ast::Expr::Linebreak(linebreak) => self.linebreak(linebreak),
-            typst::ast::Expr::Text(text) => self.text(text),
-            typst::ast::Expr::Escape(escape) => self.escape(escape),
-            typst::ast::Expr::Str(str) => self.str(str),
-            typst::ast::Expr::Raw(raw) => self.raw(raw),
-            typst::ast::Expr::Space(space) => self.space(space),
-            typst::ast::Expr::Equation(equation) => self.equation(equation, dic),
-            typst::ast::Expr::Math(math) => self.math(math, dic),
-            typst::ast::Expr::Ident(ident) => self.ident(ident, dic),
-            typst::ast::Expr::MathIdent(ident) => self.math_ident(ident, dic),
-            typst::ast::Expr::MathDelimited(delimited) => self.math_delimited(delimited, dic),
-            typst::ast::Expr::MathAttach(attach) => self.math_attach(attach, dic),
-            typst::ast::Expr::FieldAccess(access) => self.field_access(access, dic),
-            typst::ast::Expr::FuncCall(call) => self.func_call(call, dic),
-            typst::ast::Expr::Shorthand(shorthand) => self.shorthand(shorthand),
-            typst::ast::Expr::MathAlignPoint(point) => self.math_align_point(point),
-            typst::ast::Expr::MathFrac(frac) => self.math_frac(frac, dic),
-            typst::ast::Expr::MathRoot(root) => self.math_root(root, dic),
-            typst::ast::Expr::SmartQuote(quote) => self.smart_quote(quote),
-            typst::ast::Expr::Strong(strong) => self.strong(strong, dic),
-            typst::ast::Expr::Emph(emph) => self.emph(emph, dic),
-            typst::ast::Expr::Link(link) => self.link(link),
-            typst::ast::Expr::Label(label) => self.label(label),
-            typst::ast::Expr::Ref(ref_) => self.ref_(ref_),
-            //
-            typst::ast::Expr::Parbreak(_) => Err(FromTypstError::unexpected_node("par")),
-            typst::ast::Expr::Heading(_) => Err(FromTypstError::unexpected_node("heading")),
-            typst::ast::Expr::List(_) => Err(FromTypstError::unexpected_node("list")),
-            typst::ast::Expr::Enum(_) => Err(FromTypstError::unexpected_node("enum")),
-            typst::ast::Expr::Term(_) => Err(FromTypstError::unexpected_node("term")),
-            typst::ast::Expr::None(_) => Err(FromTypstError::unexpected_node("none")),
-            typst::ast::Expr::Auto(_) => Err(FromTypstError::unexpected_node("auto")),
-            typst::ast::Expr::Bool(_) => Err(FromTypstError::unexpected_node("bool")),
-            typst::ast::Expr::Int(_) => Err(FromTypstError::unexpected_node("int")),
-            typst::ast::Expr::Float(_) => Err(FromTypstError::unexpected_node("float")),
-            typst::ast::Expr::Numeric(_) => Err(FromTypstError::unexpected_node("numeric")),
-            typst::ast::Expr::Code(_) => Err(FromTypstError::unexpected_node("code")),
-            typst::ast::Expr::Content(_) => Err(FromTypstError::unexpected_node("content")),
-            typst::ast::Expr::Parenthesized(_) => {
-                Err(FromTypstError::unexpected_node("parenthesized"))
+    fn figure(&mut self, node: &typst::ast::FuncCall, ctx: &Context) -> FromTypstResult<()> {
+        let mut body = None;
+        let mut caption = None;
+        let mut kind = None;
+        let mut numbered = true;
+        for arg in node.args().items() {
+            match arg {
+                typst::ast::Arg::Pos(expr) => {
+                    let mut writer = self.child();
+                    writer.eval_expr(&expr, ctx)?;
+                    body = Some(writer.export());
+                }
+                typst::ast::Arg::Named(named) => match named.name().get().as_str() {
+                    "caption" => {
+                        let mut writer = SegmentWriter::new();
+                        writer.eval_expr(&named.expr(), ctx)?;
+                        caption = Some(writer.export());
+                    }
+                    "kind" => {
+                        kind = Some(match named.expr() {
+                            typst::ast::Expr::Ident(ident) if ident.get() == "image" => {
+                                mathlog::FigureKind::Image
+                            }
+                            typst::ast::Expr::Ident(ident) if ident.get() == "table" => {
+                                mathlog::FigureKind::Table
+                            }
+                            _ => {
+                                return Err(FromTypstError::invalid_arg(
+                                    "figure",
+                                    "`kind` must be `image` or `table`".to_string(),
+                                )
+                                .or_span(named.expr().span()))
+                            }
+                        })
+                    }
+                    "numbering" => {
+                        numbered = !matches!(named.expr(), typst::ast::Expr::None(_));
+                    }
+                    _ if ignore_appearance_arg("figure", &named, ctx) => {}
+                    name => {
+                        return Err(
+                            FromTypstError::unknown_named_arg("figure", name).or_span(named.span())
+                        )
+                    }
+                },
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(
+                        FromTypstError::spread_arg_unsupported("figure").or_span(spread.span())
+                    )
+                }
             }
-            typst::ast::Expr::Array(_) => Err(FromTypstError::unexpected_node("array")),
-            typst::ast::Expr::Dict(_) => Err(FromTypstError::unexpected_node("dict")),
-            typst::ast::Expr::Unary(_) => Err(FromTypstError::unexpected_node("unary")),
-            typst::ast::Expr::Binary(_) => Err(FromTypstError::unexpected_node("binary")),
+        }
+        let Some(mut body) = body else {
+            return Err(FromTypstError::missing_arg("figure", "body"));
+        };
+
+        // like Typst, the kind is determined by the content unless it is given explicitly
+        let kind = kind.unwrap_or_else(|| {
+            let mut kind = None;
+            for paragraph in &mut body {
+                paragraph.segments.visit_mut(&mut |segment| match segment {
+                    mathlog::Segment::Image(_) => {
+                        kind.get_or_insert(mathlog::FigureKind::Image);
+                    }
+                    mathlog::Segment::Table(_) => {
+                        kind.get_or_insert(mathlog::FigureKind::Table);
+                    }
+                    _ => {}
+                });
+            }
+            kind.unwrap_or(mathlog::FigureKind::Image)
+        });
+
+        self.push_paragraph_if_not_empty();
+        self.push_segment(mathlog::Segment::Figure(mathlog::Figure {
+            kind,
+            body,
+            caption,
+            numbered,
+            label: None,
+            number: None,
+        }));
+        self.push_paragraph();
+        Ok(())
+    }
+
+    /// Converts `#table` and `#grid` alike into a Markdown pipe table.
+    /// Without a `table.header`, the first row is used as the header.
+    fn table(
+        &mut self,
+        node: &typst::ast::FuncCall,
+        ctx: &Context,
+        func: &str,
+    ) -> FromTypstResult<()> {
+        let mut columns = 1;
+        let mut align = vec![];
+        let mut header = None;
+        let mut cells = vec![];
+        for arg in node.args().items() {
+            match arg {
+                typst::ast::Arg::Pos(typst::ast::Expr::FuncCall(call))
+                    if field_call_name(&call, func) == Some("header") =>
+                {
+                    let mut row = vec![];
+                    for arg in call.args().items() {
+                        match arg {
+                            typst::ast::Arg::Pos(expr) => {
+                                row.push(table_cell(&expr, ctx, func)?);
+                            }
+                            typst::ast::Arg::Named(_) => {}
+                            typst::ast::Arg::Spread(spread) => {
+                                return Err(FromTypstError::spread_arg_unsupported(func)
+                                    .or_span(spread.span()))
+                            }
+                        }
+                    }
+                    header = Some(row);
+                }
+                typst::ast::Arg::Pos(typst::ast::Expr::FuncCall(call))
+                    if matches!(field_call_name(&call, func), Some("hline" | "vline")) => {}
+                typst::ast::Arg::Pos(expr) => cells.push(table_cell(&expr, ctx, func)?),
+                typst::ast::Arg::Named(named) => match named.name().get().as_str() {
+                    "columns" => {
+                        let mut expr = named.expr();
+                        while let typst::ast::Expr::Parenthesized(parenthesized) = expr {
+                            expr = parenthesized.expr();
+                        }
+                        columns = match expr {
+                            typst::ast::Expr::Int(int) if int.get() > 0 => int.get() as usize,
+                            typst::ast::Expr::Array(array) if array.items().next().is_some() => {
+                                array.items().count()
+                            }
+                            typst::ast::Expr::Auto(_) | typst::ast::Expr::Numeric(_) => 1,
+                            _ => {
+                                return Err(FromTypstError::invalid_arg(
+                                    func,
+                                    "`columns` must be a positive integer or a non-empty array"
+                                        .to_string(),
+                                )
+                                .or_span(named.expr().span()))
+                            }
+                        }
+                    }
+                    "align" => {
+                        align = match named.expr() {
+                            typst::ast::Expr::Array(array) => array
+                                .items()
+                                .map(|item| match item {
+                                    typst::ast::ArrayItem::Pos(expr) => column_align(&expr, func),
+                                    typst::ast::ArrayItem::Spread(spread) => {
+                                        Err(FromTypstError::spread_arg_unsupported(func)
+                                            .or_span(spread.span()))
+                                    }
+                                })
+                                .collect::<FromTypstResult<_>>()?,
+                            expr => vec![column_align(&expr, func)?],
+                        }
+                    }
+                    _ if ignore_appearance_arg(func, &named, ctx) => {}
+                    name => {
+                        return Err(
+                            FromTypstError::unknown_named_arg(func, name).or_span(named.span())
+                        )
+                    }
+                },
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(FromTypstError::spread_arg_unsupported(func).or_span(spread.span()))
+                }
+            }
+        }
+
+        let align = match align.len() {
+            0 => vec![mathlog::ColumnAlign::Default; columns],
+            1 => vec![align[0]; columns],
+            _ => {
+                align.resize(columns, mathlog::ColumnAlign::Default);
+                align
+            }
+        };
+        let mut rows = cells
+            .chunks(columns)
+            .map(|row| row.to_vec())
+            .collect::<Vec<_>>();
+        let mut header = match header {
+            Some(header) if header.len() > columns => {
+                return Err(FromTypstError::invalid_arg(
+                    func,
+                    "a header of multiple rows cannot be expressed in a Markdown table".to_string(),
+                ))
+            }
+            Some(header) => header,
+            None if rows.is_empty() => vec![],
+            None => rows.remove(0),
+        };
+        header.resize(columns, mathlog::Segments::new());
+        for row in &mut rows {
+            row.resize(columns, mathlog::Segments::new());
+        }
+
+        self.push_paragraph_if_not_empty();
+        self.push_segment(mathlog::Segment::Table(mathlog::Table {
+            align,
+            header,
+            body: rows,
+        }));
+        self.push_paragraph();
+        Ok(())
+    }
+}
+
+impl SegmentWriter {
+    /// Functions called in markup that produce inline content.
+    const INLINE_FUNCS: [&'static str; 3] = ["link", "image", "footnote"];
+
+    fn markup(&mut self, node: &typst::ast::Markup, ctx: &Context) -> FromTypstResult<()> {
+        let exprs = node.exprs().collect::<Vec<_>>();
+        for (i, node) in exprs.iter().enumerate() {
+            if joins_words(&exprs, i) {
+                ctx.warn(FromTypstWarningKind::SpaceDropped, node.span());
+            }
+            let len = self.segments.0.len();
+            if let Err(err) = self.expr(node, ctx) {
+                ctx.record(err)?;
+                // drop what the node wrote before failing
+                self.segments.0.truncate(len);
+                self.push_segment(placeholder(node, false));
+            }
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, node: &typst::ast::Expr, ctx: &Context) -> FromTypstResult<()> {
+        let result = match node {
+            typst::ast::Expr::Linebreak(linebreak) => self.linebreak(linebreak),
+            typst::ast::Expr::Text(text) => self.text(text),
+            typst::ast::Expr::Escape(escape) => self.escape(escape),
+            typst::ast::Expr::Str(str) => self.str(str),
+            typst::ast::Expr::Raw(raw) => self.raw(raw),
+            typst::ast::Expr::Space(space) => self.space(space),
+            typst::ast::Expr::Equation(equation) => self.equation(equation, ctx),
+            typst::ast::Expr::Math(math) => self.math(math, ctx),
+            typst::ast::Expr::Ident(ident) => self.ident(ident, ctx),
+            typst::ast::Expr::MathIdent(ident) => self.math_ident(ident, ctx),
+            typst::ast::Expr::MathDelimited(delimited) => self.math_delimited(delimited, ctx),
+            typst::ast::Expr::MathAttach(attach) => self.math_attach(attach, ctx),
+            typst::ast::Expr::FieldAccess(access) => self.field_access(access, ctx),
+            typst::ast::Expr::FuncCall(call) => self.func_call(call, ctx),
+            typst::ast::Expr::Shorthand(shorthand) => self.shorthand(shorthand),
+            typst::ast::Expr::MathAlignPoint(point) => self.math_align_point(point),
+            typst::ast::Expr::MathFrac(frac) => self.math_frac(frac, ctx),
+            typst::ast::Expr::MathRoot(root) => self.math_root(root, ctx),
+            typst::ast::Expr::SmartQuote(quote) => self.smart_quote(quote),
+            typst::ast::Expr::Strong(strong) => self.strong(strong, ctx),
+            typst::ast::Expr::Emph(emph) => self.emph(emph, ctx),
+            typst::ast::Expr::Link(link) => self.link(link),
+            typst::ast::Expr::Label(label) => self.label(label),
+            typst::ast::Expr::Ref(ref_) => self.ref_(ref_, ctx),
+            //
+            typst::ast::Expr::Parbreak(_) => Err(FromTypstError::unexpected_node("par")),
+            typst::ast::Expr::Heading(_) => Err(FromTypstError::unexpected_node("heading")),
+            typst::ast::Expr::List(_) => Err(FromTypstError::unexpected_node("list")),
+            typst::ast::Expr::Enum(_) => Err(FromTypstError::unexpected_node("enum")),
+            typst::ast::Expr::Term(_) => Err(FromTypstError::unexpected_node("term")),
+            typst::ast::Expr::None(_) => Err(FromTypstError::unexpected_node("none")),
+            typst::ast::Expr::Auto(_) => Err(FromTypstError::unexpected_node("auto")),
+            typst::ast::Expr::Bool(_) => Err(FromTypstError::unexpected_node("bool")),
+            typst::ast::Expr::Int(_) => Err(FromTypstError::unexpected_node("int")),
+            typst::ast::Expr::Float(_) => Err(FromTypstError::unexpected_node("float")),
+            typst::ast::Expr::Numeric(_) => Err(FromTypstError::unexpected_node("numeric")),
+            typst::ast::Expr::Code(_) => Err(FromTypstError::unexpected_node("code")),
+            typst::ast::Expr::Content(_) => Err(FromTypstError::unexpected_node("content")),
+            typst::ast::Expr::Parenthesized(_) => {
+                Err(FromTypstError::unexpected_node("parenthesized"))
+            }
+            typst::ast::Expr::Array(_) => Err(FromTypstError::unexpected_node("array")),
+            typst::ast::Expr::Dict(_) => Err(FromTypstError::unexpected_node("dict")),
+            typst::ast::Expr::Unary(_) => Err(FromTypstError::unexpected_node("unary")),
+            typst::ast::Expr::Binary(_) => Err(FromTypstError::unexpected_node("binary")),
             typst::ast::Expr::Closure(_) => Err(FromTypstError::unexpected_node("closure")),
             typst::ast::Expr::Let(_) => Err(FromTypstError::unexpected_node("let")),
             typst::ast::Expr::DestructAssign(_) => {
                 Err(FromTypstError::unexpected_node("destruct_assign"))
             }
-            typst::ast::Expr::Set(_) => Err(FromTypstError::unexpected_node("set")),
-            typst::ast::Expr::Show(_) => Err(FromTypstError::unexpected_node("show")),
+            // styles cannot be changed in a line
+            typst::ast::Expr::Set(set) => {
+                ctx.warn(FromTypstWarningKind::SetRuleIgnored, set.span());
+                Ok(())
+            }
+            typst::ast::Expr::Show(show) => self.show(show, ctx),
             typst::ast::Expr::Conditional(_) => Err(FromTypstError::unexpected_node("conditional")),
             typst::ast::Expr::While(_) => Err(FromTypstError::unexpected_node("while")),
             typst::ast::Expr::For(_) => Err(FromTypstError::unexpected_node("for")),
@@ -568,9 +1413,15 @@ impl SegmentWriter {
             typst::ast::Expr::Continue(_) => Err(FromTypstError::unexpected_node("continue")),
             typst::ast::Expr::Return(_) => Err(FromTypstError::unexpected_node("return")),
             // _ => todo!("{:?}", node),
-            typst::ast::Expr::MathPrimes(_) => Err(FromTypstError::unexpected_node("math primes")),
+            typst::ast::Expr::MathPrimes(primes) => {
+                self.push_segment(mathlog::Segment::Text(mathlog::Text(
+                    "'".repeat(primes.count()),
+                )));
+                Ok(())
+            }
             typst::ast::Expr::Contextual(_) => Err(FromTypstError::unexpected_node("contextual")),
-        }
+        };
+        result.map_err(|err| err.or_span(node.span()))
     }
 
     fn linebreak(&mut self, _node: &typst::ast::Linebreak) -> FromTypstResult<()> {
@@ -599,24 +1450,27 @@ impl SegmentWriter {
 
     fn str(&mut self, node: &typst::ast::Str) -> FromTypstResult<()> {
         let s = node.get().to_string();
-        self.push_segment(mathlog::Segment::Command(mathlog::Command {
-            name: "mathrm".to_string(),
-            args: vec![mathlog::Arg {
-                is_optional: false,
-                content: mathlog::Segments(vec![mathlog::Segment::Text(mathlog::Text(s))]),
-            }],
-        }));
+        self.push_segment(mathlog::Segment::MathText(s));
         Ok(())
     }
 
     fn raw(&mut self, node: &typst::ast::Raw) -> FromTypstResult<()> {
         // removed: https://github.com/typst/typst/commit/030041466b5b8453ca23e43a6385f4592f78a56c between 0.10.0 and 0.11.0
         // let s = node.text().to_string();
-        let s = node.lines()
+        let s = node
+            .lines()
             .map(|text| text.get().to_string())
             .collect::<Vec<String>>()
             .join("\n");
-        self.push_segment(mathlog::Segment::CodeInline(mathlog::CodeInline(s)));
+        if node.block() {
+            let lang = node.lang().map(|lang| lang.get().to_string());
+            self.push_segment(mathlog::Segment::CodeBlock(mathlog::CodeBlock {
+                lang,
+                code: s,
+            }));
+        } else {
+            self.push_segment(mathlog::Segment::CodeInline(mathlog::CodeInline(s)));
+        }
         Ok(())
     }
 
@@ -626,19 +1480,19 @@ impl SegmentWriter {
         Ok(())
     }
 
-    fn strong(&mut self, node: &typst::ast::Strong, dic: &Dictionary) -> FromTypstResult<()> {
+    fn strong(&mut self, node: &typst::ast::Strong, ctx: &Context) -> FromTypstResult<()> {
         let body = node.body();
         let mut writer = SegmentWriter::new();
-        writer.markup(&body, dic)?;
+        writer.markup(&body, ctx)?;
         let content = writer.export();
         self.push_segment(mathlog::Segment::Strong(mathlog::Strong { content }));
         Ok(())
     }
 
-    fn emph(&mut self, node: &typst::ast::Emph, dic: &Dictionary) -> FromTypstResult<()> {
+    fn emph(&mut self, node: &typst::ast::Emph, ctx: &Context) -> FromTypstResult<()> {
         let body = node.body();
         let mut writer = SegmentWriter::new();
-        writer.markup(&body, dic)?;
+        writer.markup(&body, ctx)?;
         let content = writer.export();
         self.push_segment(mathlog::Segment::Emph(mathlog::Emph { content }));
         Ok(())
@@ -649,22 +1503,35 @@ impl SegmentWriter {
         Ok(())
     }
 
-    fn equation(&mut self, node: &typst::ast::Equation, dic: &Dictionary) -> FromTypstResult<()> {
+    fn show(&mut self, node: &typst::ast::ShowRule, ctx: &Context) -> FromTypstResult<()> {
+        // ignore but leave an export comment
+        ctx.warn(FromTypstWarningKind::ShowRuleIgnored, node.span());
+        self.push_segment(mathlog::Segment::ExportComment("#show".to_string()));
+        Ok(())
+    }
+
+    fn equation(&mut self, node: &typst::ast::Equation, ctx: &Context) -> FromTypstResult<()> {
         let body = node.body();
         let mut writer = SegmentWriter::new();
-        writer.math(&body, dic)?;
+        writer.math(&body, ctx)?;
         let content = writer.export();
         self.push_segment(if node.block() {
-            mathlog::Segment::MathDisplay(mathlog::MathDisplay { content })
+            mathlog::Segment::MathDisplay(mathlog::MathDisplay {
+                content,
+                numbering: None,
+                label: None,
+                number: None,
+            })
         } else {
             mathlog::Segment::MathInline(mathlog::MathInline { content })
         });
         Ok(())
     }
 
-    fn ident(&mut self, node: &typst::ast::Ident, dic: &Dictionary) -> FromTypstResult<()> {
+    fn ident(&mut self, node: &typst::ast::Ident, ctx: &Context) -> FromTypstResult<()> {
         let ident = node.get().to_string();
-        let command = dic
+        let command = ctx
+            .dic
             .idents
             .get(&ident)
             .ok_or(FromTypstError::unsupported_ident(vec![ident]))?
@@ -673,13 +1540,10 @@ impl SegmentWriter {
         Ok(())
     }
 
-    fn math_ident(
-        &mut self,
-        node: &typst::ast::MathIdent,
-        dic: &Dictionary,
-    ) -> FromTypstResult<()> {
+    fn math_ident(&mut self, node: &typst::ast::MathIdent, ctx: &Context) -> FromTypstResult<()> {
         let ident = node.get().to_string();
-        let command = dic
+        let command = ctx
+            .dic
             .idents
             .get(&ident)
             .ok_or(FromTypstError::unsupported_ident(vec![ident]))?
@@ -691,38 +1555,38 @@ impl SegmentWriter {
     fn field_access(
         &mut self,
         node: &typst::ast::FieldAccess,
-        dic: &Dictionary,
+        ctx: &Context,
     ) -> FromTypstResult<()> {
         fn get_mod<'a>(
             node: &typst::ast::Expr,
             dic: &'a Dictionary,
-        ) -> (Option<&'a Dictionary>, Vec<String>) {
+        ) -> FromTypstResult<(Option<&'a Dictionary>, Vec<String>)> {
             match node {
                 typst::ast::Expr::FieldAccess(access) => {
                     let target = access.target();
                     let field = access.field();
                     let field_name = field.get().to_string();
-                    let (dic, mut fields) = get_mod(&target, dic);
+                    let (dic, mut fields) = get_mod(&target, dic)?;
                     fields.push(field_name);
-                    (dic, fields)
+                    Ok((dic, fields))
                 }
                 typst::ast::Expr::Ident(ident) => {
                     let ident = ident.get().to_string();
                     let dic = dic.modules.get(&ident);
-                    (dic, vec![ident])
+                    Ok((dic, vec![ident]))
                 }
                 typst::ast::Expr::MathIdent(ident) => {
                     let ident = ident.get().to_string();
                     let dic = dic.modules.get(&ident);
-                    (dic, vec![ident])
+                    Ok((dic, vec![ident]))
                 }
-                _ => panic!("unexpected node in field access: {:?}", node),
+                _ => Err(FromTypstError::invalid_field_access(node)),
             }
         }
 
         let target = node.target();
         let field = node.field();
-        let (mod_dic, path) = get_mod(&target, dic);
+        let (mod_dic, path) = get_mod(&target, ctx.dic)?;
         let Some(mod_dic) = mod_dic else {
             return Err(FromTypstError::unsupported_ident(path));
         };
@@ -736,30 +1600,73 @@ impl SegmentWriter {
         Ok(())
     }
 
-    fn func_call(&mut self, node: &typst::ast::FuncCall, dic: &Dictionary) -> FromTypstResult<()> {
+    fn func_call(&mut self, node: &typst::ast::FuncCall, ctx: &Context) -> FromTypstResult<()> {
         // TODO: add special cases, for example, for `#strong`
         match node.callee() {
             typst::ast::Expr::Ident(ident) => {
                 let ident = ident.get();
                 if let Some(kind) = mathlog::EnvKind::from_name(ident) {
-                    Err(FromTypstError::env_in_segments(kind.name()))
+                    Err(FromTypstError::env_in_segments(kind.typst_name()))
                 } else {
-                    todo!(); // TODO: handle other cases
+                    match ident.as_str() {
+                        "link" => self.link_call(&node.args(), ctx),
+                        "image" => self.image_call(&node.args(), ctx),
+                        "footnote" => self.footnote_call(&node.args(), ctx),
+                        "quote" => self.quote_call(&node.args(), ctx),
+                        _ => Err(FromTypstError::unsupported_func_call(vec![
+                            ident.to_string()
+                        ])),
+                    }
                 }
             }
             typst::ast::Expr::MathIdent(ident) => {
                 let ident = ident.get();
                 match &*ident.to_string() {
-                    "upright" => self.single_call("mathrm", &node.args(), dic),
-                    "italic" => self.single_call("mathit", &node.args(), dic),
-                    "bold" => self.single_call("boldsymbol", &node.args(), dic),
-                    "cal" => self.single_call("mathcal", &node.args(), dic),
-                    "bb" => self.single_call("mathbb", &node.args(), dic),
-                    "frak" => self.single_call("mathfrak", &node.args(), dic),
-                    _ => todo!("{:?}", node),
+                    name if style_change(name).is_some() => {
+                        self.style_call(name, &node.args(), ctx)
+                    }
+                    name if single_call_command(name).is_some() => {
+                        self.single_call(name, &node.args(), ctx)
+                    }
+                    name if SIZE_FUNCS.iter().any(|(typst, _)| *typst == name) => {
+                        self.size_call(name, &node.args(), ctx)
+                    }
+                    "root" => self.root_call(&node.args(), ctx),
+                    "frac" => self.frac_call(&node.args(), ctx),
+                    "mat" | "vec" | "cases" => self.math_matrix(ident, &node.args(), ctx),
+                    "binom" => self.math_binom(&node.args(), ctx),
+                    "overbrace" | "underbrace" | "overbracket" | "underbracket" => {
+                        self.math_brace(ident, &node.args(), ctx)
+                    }
+                    "cancel" => self.math_cancel(&node.args(), ctx),
+                    "lr" | "abs" | "norm" | "floor" | "ceil" | "round" => {
+                        self.lr_call(ident, &node.args(), ctx)
+                    }
+                    "mid" => self.mid_call(&node.args(), ctx),
+                    "op" => self.op_call(&node.args(), ctx),
+                    // without scripts, only the body remains
+                    "limits" | "scripts" => {
+                        let content = limits_base(ident, &node.args(), ctx)?;
+                        for segment in content.0 {
+                            self.push_segment(segment);
+                        }
+                        Ok(())
+                    }
+                    "class" => self.class_call(&node.args(), ctx),
+                    "attach" => self.attach_call(&node.args(), ctx),
+                    "accent" => self.accent_call(&node.args(), ctx),
+                    name if accent_by_name(name).is_some() => self.accent(name, &node.args(), ctx),
+                    _ => self.math_application(node, ctx),
                 }
             }
-            _ => todo!("{:?}", node),
+            typst::ast::Expr::FieldAccess(access) => match math_field_path(&access) {
+                // e.g. `dot.double(x)` and `arrow.l(x)`
+                Some(name) if accent_by_name(&name).is_some() => {
+                    self.accent(&name, &node.args(), ctx)
+                }
+                _ => self.math_application(node, ctx),
+            },
+            callee => Err(FromTypstError::unsupported_callee(&callee)),
         }
     }
 
@@ -773,49 +1680,268 @@ impl SegmentWriter {
     fn math_delimited(
         &mut self,
         node: &typst::ast::MathDelimited,
-        dic: &Dictionary,
+        ctx: &Context,
     ) -> FromTypstResult<()> {
         let open = node.open();
         let body = node.body();
         let close = node.close();
 
         let mut open_writer = SegmentWriter::new();
-        open_writer.expr(&open, dic)?;
+        open_writer.expr(&open, ctx)?;
         let open_content = open_writer.export();
 
         let mut body_writer = SegmentWriter::new();
-        body_writer.math(&body, dic)?;
+        body_writer.math(&body, ctx)?;
         let body_content = body_writer.export();
 
         let mut close_writer = SegmentWriter::new();
-        close_writer.expr(&close, dic)?;
+        close_writer.expr(&close, ctx)?;
         let close_content = close_writer.export();
 
         self.push_segment(mathlog::Segment::MathDelimited(mathlog::MathDelimited {
             open: open_content,
             body: body_content,
             close: close_content,
+            size: mathlog::DelimiterSize::Auto,
         }));
 
         Ok(())
     }
 
-    fn math_attach(
+    /// `lr` and the functions putting fixed delimiters such as `abs` and `floor`.
+    fn lr_call(
         &mut self,
-        node: &typst::ast::MathAttach,
-        dic: &Dictionary,
+        func: &str,
+        args: &typst::ast::Args,
+        ctx: &Context,
+    ) -> FromTypstResult<()> {
+        let mut size = mathlog::DelimiterSize::Auto;
+        let [body] = positional_args(func, args, ["body"], |named| {
+            if named.name().get() != "size" {
+                return Ok(false);
+            }
+            size = delimiter_size(func, &named.expr())?;
+            Ok(true)
+        })?;
+
+        let convert = |exprs: &[typst::ast::Expr]| -> FromTypstResult<mathlog::Segments> {
+            let mut writer = SegmentWriter::new();
+            for expr in exprs {
+                writer.expr(expr, ctx)?;
+            }
+            Ok(writer.export())
+        };
+        let raw = |s: &str| {
+            mathlog::Segments(vec![mathlog::Segment::RawCommand(mathlog::RawCommand(
+                s.to_string(),
+            ))])
+        };
+        let (open, body, close) = match (func, &body) {
+            ("abs", _) => (raw("|"), convert(&[body])?, raw("|")),
+            ("norm", _) => (raw("\\|"), convert(&[body])?, raw("\\|")),
+            ("floor", _) => (raw("\\lfloor"), convert(&[body])?, raw("\\rfloor")),
+            ("ceil", _) => (raw("\\lceil"), convert(&[body])?, raw("\\rceil")),
+            ("round", _) => (raw("\\lfloor"), convert(&[body])?, raw("\\rceil")),
+            (_, typst::ast::Expr::MathDelimited(delimited)) => (
+                convert(&[delimited.open()])?,
+                convert(&delimited.body().exprs().collect::<Vec<_>>())?,
+                convert(&[delimited.close()])?,
+            ),
+            // the first and the last are the delimiters, as in `lr(|x|)`
+            (_, typst::ast::Expr::Math(math)) => {
+                let exprs = math
+                    .exprs()
+                    .filter(|expr| !matches!(expr, typst::ast::Expr::Space(_)))
+                    .collect::<Vec<_>>();
+                match &exprs[..] {
+                    [open, body @ .., close] if is_delimiter(open) && is_delimiter(close) => {
+                        (convert(&[*open])?, convert(body)?, convert(&[*close])?)
+                    }
+                    _ => (
+                        mathlog::Segments::new(),
+                        convert(&exprs)?,
+                        mathlog::Segments::new(),
+                    ),
+                }
+            }
+            _ => (
+                mathlog::Segments::new(),
+                convert(&[body])?,
+                mathlog::Segments::new(),
+            ),
+        };
+        self.push_segment(mathlog::Segment::MathDelimited(mathlog::MathDelimited {
+            open,
+            body,
+            close,
+            size,
+        }));
+        Ok(())
+    }
+
+    /// A call of a function that is not a math function, such as `phi(a, b)`, as an application.
+    fn math_application(
+        &mut self,
+        node: &typst::ast::FuncCall,
+        ctx: &Context,
     ) -> FromTypstResult<()> {
-        let base = node.base();
-        let bottom = node.bottom();
-        let top = node.top();
+        let callee = node.callee();
+        let callee_name = match &callee {
+            typst::ast::Expr::MathIdent(ident) => ident.get().to_string(),
+            typst::ast::Expr::FieldAccess(access) => math_field_path(access).unwrap_or_default(),
+            _ => String::new(),
+        };
+        match &callee {
+            typst::ast::Expr::MathIdent(ident)
+                if ident.get().chars().count() == 1
+                    && !ctx.dic.idents.contains_key(ident.get().as_str()) =>
+            {
+                self.push_segment(mathlog::Segment::Text(mathlog::Text(
+                    ident.get().to_string(),
+                )))
+            }
+            _ => self.expr(&callee, ctx)?,
+        }
+
+        let mut body = mathlog::Segments::new();
+        for (i, arg) in node.args().items().enumerate() {
+            let expr = match arg {
+                // `f(a, b; c, d)`
+                typst::ast::Arg::Pos(typst::ast::Expr::Array(_)) => {
+                    return Err(FromTypstError::invalid_arg(
+                        &callee_name,
+                        "arguments separated by `;` are not supported".to_string(),
+                    ))
+                }
+                typst::ast::Arg::Pos(expr) => expr,
+                typst::ast::Arg::Named(named) => {
+                    return Err(
+                        FromTypstError::unknown_named_arg(&callee_name, named.name().get())
+                            .or_span(named.span()),
+                    )
+                }
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(
+                        FromTypstError::spread_arg_unsupported(&callee_name).or_span(spread.span())
+                    )
+                }
+            };
+            if i != 0 {
+                body.push(mathlog::Segment::Text(mathlog::Text(",".to_string())));
+            }
+            let mut writer = SegmentWriter::new();
+            writer.expr(&expr, ctx)?;
+            body.0.extend(writer.export().0);
+        }
+        let paren =
+            |s: &str| mathlog::Segments(vec![mathlog::Segment::Text(mathlog::Text(s.to_string()))]);
+        self.push_segment(mathlog::Segment::MathDelimited(mathlog::MathDelimited {
+            open: paren("("),
+            body,
+            close: paren(")"),
+            size: mathlog::DelimiterSize::Auto,
+        }));
+        Ok(())
+    }
 
-        let mut base_writer = SegmentWriter::new();
-        base_writer.expr(&base, dic)?;
-        let base_content = base_writer.export();
+    /// `op(text, limits: bool)`, where `limits: #true` places the scripts below and above.
+    fn op_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let mut limits = false;
+        let [text] = positional_args("op", args, ["text"], |named| {
+            if named.name().get() != "limits" {
+                return Ok(false);
+            }
+            limits = matches!(named.expr(), typst::ast::Expr::Bool(b) if b.get());
+            Ok(true)
+        })?;
+
+        let content = match text {
+            // the name itself, not `\mathrm{...}` of a string in math
+            typst::ast::Expr::Str(s) => mathlog::Segments(vec![mathlog::Segment::Text(
+                mathlog::Text(s.get().to_string()),
+            )]),
+            _ => {
+                let mut writer = SegmentWriter::new();
+                writer.expr(&text, ctx)?;
+                writer.export()
+            }
+        };
+        self.push_segment(operator_name(content, limits));
+        Ok(())
+    }
+
+    /// `class(class, body)` as `\mathrel{...}` and so on.
+    fn class_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let [class, body] = positional_args("class", args, ["class", "body"], |_| Ok(false))?;
+
+        let typst::ast::Expr::Str(class) = class else {
+            return Err(FromTypstError::invalid_arg(
+                "class",
+                "the class must be a string".to_string(),
+            )
+            .or_span(class.span()));
+        };
+        let class_span = class.span();
+        let name = match class.get().as_str() {
+            "normal" | "unary" | "fence" => "mathord",
+            "punctuation" => "mathpunct",
+            "opening" => "mathopen",
+            "closing" => "mathclose",
+            "large" => "mathop",
+            "relation" => "mathrel",
+            "binary" | "vary" => "mathbin",
+            class => {
+                return Err(FromTypstError::invalid_arg(
+                    "class",
+                    format!("unknown class {:?}", class),
+                )
+                .or_span(class_span))
+            }
+        };
+
+        let mut writer = SegmentWriter::new();
+        writer.expr(&body, ctx)?;
+        self.push_segment(mathlog::Segment::Command(mathlog::Command {
+            name: name.to_string(),
+            args: vec![mathlog::Arg {
+                is_optional: false,
+                content: writer.export(),
+            }],
+        }));
+        Ok(())
+    }
+
+    fn mid_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let [body] = positional_args("mid", args, ["body"], |_| Ok(false))?;
+        let mut writer = SegmentWriter::new();
+        writer.expr(&body, ctx)?;
+        self.push_segment(mathlog::Segment::MathMiddle(writer.export()));
+        Ok(())
+    }
+
+    fn math_attach(&mut self, node: &typst::ast::MathAttach, ctx: &Context) -> FromTypstResult<()> {
+        let mut base = node.base();
+        let mut bottom = node.bottom();
+        let mut top = node.top();
+        let mut primes = node.primes().map_or(0, |primes| primes.count());
+        // `f''_i^2` is parsed as nested attachments, whose scripts are put on `f''` together
+        while let typst::ast::Expr::MathAttach(inner) = base {
+            if (primes > 0 && inner.primes().is_some())
+                || (bottom.is_some() && inner.bottom().is_some())
+                || (top.is_some() && inner.top().is_some())
+            {
+                break;
+            }
+            primes += inner.primes().map_or(0, |primes| primes.count());
+            bottom = bottom.or(inner.bottom());
+            top = top.or(inner.top());
+            base = inner.base();
+        }
+        let (base, limits) = attach_base(&base, ctx)?;
 
         let bottom_content = if let Some(bottom) = bottom {
             let mut bottom_writer = SegmentWriter::new();
-            bottom_writer.expr(&bottom, dic)?;
+            bottom_writer.expr(&bottom, ctx)?;
             Some(bottom_writer.export())
         } else {
             None
@@ -823,36 +1949,101 @@ impl SegmentWriter {
 
         let top_content = if let Some(top) = top {
             let mut top_writer = SegmentWriter::new();
-            top_writer.expr(&top, dic)?;
+            top_writer.expr(&top, ctx)?;
             Some(top_writer.export())
         } else {
             None
         };
 
         self.push_segment(mathlog::Segment::MathAttach(mathlog::MathAttach {
-            base: base_content,
+            base,
             bottom: bottom_content,
             top: top_content,
+            top_left: None,
+            bottom_left: None,
+            primes,
+            limits,
         }));
 
         Ok(())
     }
 
+    /// `attach(base, t: .., b: .., tl: .., bl: .., tr: .., br: ..)`. When scripts are given both
+    /// above and at the top right, the former is put above the base by `\overset`, and so on.
+    fn attach_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let mut scripts: [Option<mathlog::Segments>; 6] = Default::default();
+        const POSITIONS: [&str; 6] = ["t", "b", "tl", "bl", "tr", "br"];
+        let [base] = positional_args("attach", args, ["base"], |named| {
+            let name = named.name().get();
+            let Some(i) = POSITIONS.iter().position(|position| position == name) else {
+                return Ok(false);
+            };
+            // `none` leaves the position empty
+            if !matches!(named.expr(), typst::ast::Expr::None(_)) {
+                let mut writer = SegmentWriter::new();
+                writer.expr(&named.expr(), ctx)?;
+                scripts[i] = Some(writer.export());
+            }
+            Ok(true)
+        })?;
+        let [top, bottom, top_left, bottom_left, top_right, bottom_right] = scripts;
+
+        let (mut base, limits) = attach_base(&base, ctx)?;
+        let (top, bottom) = match (top, bottom, top_right, bottom_right) {
+            (top, bottom, None, None) => (top, bottom),
+            (None, None, top_right, bottom_right) => (top_right, bottom_right),
+            (top, bottom, top_right, bottom_right) => {
+                for (name, script) in [("overset", top), ("underset", bottom)] {
+                    if let Some(script) = script {
+                        base =
+                            mathlog::Segments(vec![mathlog::Segment::Command(mathlog::Command {
+                                name: name.to_string(),
+                                args: vec![
+                                    mathlog::Arg {
+                                        is_optional: false,
+                                        content: script,
+                                    },
+                                    mathlog::Arg {
+                                        is_optional: false,
+                                        content: base,
+                                    },
+                                ],
+                            })]);
+                    }
+                }
+                (top_right, bottom_right)
+            }
+        };
+
+        self.push_segment(mathlog::Segment::MathAttach(mathlog::MathAttach {
+            base,
+            top,
+            bottom,
+            top_left,
+            bottom_left,
+            primes: 0,
+            limits,
+        }));
+        Ok(())
+    }
+
     fn math_align_point(&mut self, _node: &typst::ast::MathAlignPoint) -> FromTypstResult<()> {
         self.push_segment(mathlog::Segment::MathAlignPoint);
         Ok(())
     }
 
-    fn math_frac(&mut self, node: &typst::ast::MathFrac, dic: &Dictionary) -> FromTypstResult<()> {
+    /// The parser has already turned the parentheses around an operand, as in `(a+b)/c`,
+    /// into bare tokens, which Typst does not render. The same holds for scripts and radicands.
+    fn math_frac(&mut self, node: &typst::ast::MathFrac, ctx: &Context) -> FromTypstResult<()> {
         let num = node.num();
         let denom = node.denom();
 
         let mut num_writer = SegmentWriter::new();
-        num_writer.expr(&num, dic)?;
+        num_writer.expr(&num, ctx)?;
         let num_content = num_writer.export();
 
         let mut denom_writer = SegmentWriter::new();
-        denom_writer.expr(&denom, dic)?;
+        denom_writer.expr(&denom, ctx)?;
         let denom_content = denom_writer.export();
 
         self.push_segment(mathlog::Segment::Command(mathlog::Command {
@@ -872,12 +2063,12 @@ impl SegmentWriter {
         Ok(())
     }
 
-    fn math_root(&mut self, node: &typst::ast::MathRoot, dic: &Dictionary) -> FromTypstResult<()> {
+    fn math_root(&mut self, node: &typst::ast::MathRoot, ctx: &Context) -> FromTypstResult<()> {
         let index = node.index();
         let radicand = node.radicand();
 
         let mut radicand_writer = SegmentWriter::new();
-        radicand_writer.expr(&radicand, dic)?;
+        radicand_writer.expr(&radicand, ctx)?;
         let radicand_content = radicand_writer.export();
 
         let arg = if let Some(index) = index {
@@ -906,71 +2097,760 @@ impl SegmentWriter {
         Ok(())
     }
 
-    fn link(&self, _link: &typst::ast::Link) -> FromTypstResult<()> {
-        // TODO: implement
-        Err(FromTypstError::not_yet_implemented("link".to_string()))
+    fn link(&mut self, node: &typst::ast::Link) -> FromTypstResult<()> {
+        let url = node.get().to_string();
+        self.push_segment(mathlog::Segment::Link(mathlog::Link {
+            dest: mathlog::LinkDest::Url(url),
+            content: None,
+            resolved: None,
+        }));
+        Ok(())
     }
 
-    fn label(&self, _label: &typst::ast::Label) -> FromTypstResult<()> {
-        // TODO: implement
-        Err(FromTypstError::not_yet_implemented("label".to_string()))
+    fn label(&mut self, node: &typst::ast::Label) -> FromTypstResult<()> {
+        let label = node.get().to_string();
+        let slot = self
+            .segments
+            .0
+            .last_mut()
+            .and_then(|segment| segment.label_mut());
+        if let Some(slot @ None) = slot {
+            *slot = Some(label);
+        } else {
+            self.push_segment(mathlog::Segment::Label(label));
+        }
+        Ok(())
     }
 
-    fn ref_(&self, _ref_: &typst::ast::Ref) -> FromTypstResult<()> {
-        // TODO: implement
-        Err(FromTypstError::not_yet_implemented("ref".to_string()))
+    fn ref_(&mut self, node: &typst::ast::Ref, ctx: &Context) -> FromTypstResult<()> {
+        let supplement = if let Some(supplement) = node.supplement() {
+            let mut writer = SegmentWriter::new();
+            writer.markup(&supplement.body(), ctx)?;
+            Some(writer.export())
+        } else {
+            None
+        };
+        self.push_segment(mathlog::Segment::Ref(mathlog::Ref {
+            target: node.target().to_string(),
+            supplement,
+            resolved: None,
+        }));
+        Ok(())
     }
 
     //
 
-    fn math(&mut self, node: &typst::ast::Math, dic: &Dictionary) -> FromTypstResult<()> {
+    fn math(&mut self, node: &typst::ast::Math, ctx: &Context) -> FromTypstResult<()> {
         for expr in node.exprs() {
-            self.expr(&expr, dic)?;
+            let len = self.segments.0.len();
+            if let Err(err) = self.expr(&expr, ctx) {
+                ctx.record(err)?;
+                self.segments.0.truncate(len);
+                self.push_segment(math_placeholder(&expr));
+            }
         }
         Ok(())
     }
 
-    fn single_call(
-        &mut self,
-        name: &str,
-        args: &typst::ast::Args,
-        dic: &Dictionary,
-    ) -> FromTypstResult<()> {
-        if args.items().count() != 1 {
-            todo!(); // TODO: handle error
+    fn link_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let mut dest = None;
+        let mut content = None;
+        for arg in args.items() {
+            match arg {
+                typst::ast::Arg::Pos(expr) if dest.is_none() => {
+                    dest = Some(match expr {
+                        typst::ast::Expr::Str(s) => mathlog::LinkDest::Url(s.get().to_string()),
+                        typst::ast::Expr::Label(label) => {
+                            mathlog::LinkDest::Label(label.get().to_string())
+                        }
+                        _ => {
+                            return Err(FromTypstError::invalid_arg(
+                                "link",
+                                "destination must be a string or a label".to_string(),
+                            )
+                            .or_span(expr.span()))
+                        }
+                    });
+                }
+                typst::ast::Arg::Pos(expr) => {
+                    let mut writer = SegmentWriter::new();
+                    writer.eval_expr(&expr, ctx)?;
+                    content = Some(writer.export());
+                }
+                typst::ast::Arg::Named(named) => {
+                    return Err(
+                        FromTypstError::unknown_named_arg("link", named.name().get())
+                            .or_span(named.span()),
+                    )
+                }
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(
+                        FromTypstError::spread_arg_unsupported("link").or_span(spread.span())
+                    )
+                }
+            }
         }
-        let arg = args.items().next().unwrap();
-        let mut body = None; // TODO
-        match arg {
-            typst::ast::Arg::Pos(arg) => {
-                body = Some(arg);
+        let Some(dest) = dest else {
+            return Err(FromTypstError::missing_arg("link", "dest"));
+        };
+        self.push_segment(mathlog::Segment::Link(mathlog::Link {
+            dest,
+            content,
+            resolved: None,
+        }));
+        Ok(())
+    }
+
+    fn footnote_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let mut body = None;
+        for arg in args.items() {
+            match arg {
+                typst::ast::Arg::Pos(expr) => body = Some(expr),
+                typst::ast::Arg::Named(named) if ignore_appearance_arg("footnote", &named, ctx) => {
+                }
+                typst::ast::Arg::Named(named) => {
+                    return Err(
+                        FromTypstError::unknown_named_arg("footnote", named.name().get())
+                            .or_span(named.span()),
+                    )
+                }
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(
+                        FromTypstError::spread_arg_unsupported("footnote").or_span(spread.span())
+                    )
+                }
             }
-            typst::ast::Arg::Named(arg) => {
-                body = Some(arg.expr());
+        }
+        match body {
+            // `#footnote(<label>)` repeats the mark of a labelled footnote
+            Some(typst::ast::Expr::Label(label)) => {
+                self.push_segment(mathlog::Segment::Ref(mathlog::Ref {
+                    target: label.get().to_string(),
+                    supplement: None,
+                    resolved: None,
+                }));
+            }
+            Some(body) => {
+                let mut writer = SegmentWriter::new();
+                writer.eval_expr(&body, ctx)?;
+                self.push_segment(mathlog::Segment::Footnote(mathlog::Footnote {
+                    content: writer.export(),
+                    label: None,
+                    number: None,
+                }));
             }
-            _ => todo!(), // TODO: handle error
+            None => return Err(FromTypstError::missing_arg("footnote", "body")),
         }
-        let Some(body) = body else {
-            todo!(); // TODO: handle error
-        };
+        Ok(())
+    }
+
+    fn quote_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let args = QuoteArgs::new(args, ctx)?;
+        self.inline_quote(args, ctx)
+    }
 
+    fn inline_quote(&mut self, args: QuoteArgs, ctx: &Context) -> FromTypstResult<()> {
+        if args.block {
+            return Err(FromTypstError::invalid_arg(
+                "quote",
+                "a block quote cannot be in a line".to_string(),
+            ));
+        }
         let mut writer = SegmentWriter::new();
-        writer.expr(&body, dic)?;
-        let content = writer.export();
-        self.push_segment(mathlog::Segment::Command(mathlog::Command {
-            name: name.to_string(),
-            args: vec![mathlog::Arg {
-                is_optional: false,
-                content,
+        writer.eval_expr(&args.body, ctx)?;
+        self.push_segment(mathlog::Segment::Quote(mathlog::Quote {
+            block: false,
+            quotes: args.quotes.unwrap_or(true),
+            contents: vec![mathlog::Paragraph {
+                segments: writer.export(),
             }],
+            attribution: args.attribution,
         }));
         Ok(())
     }
-}
 
-impl ParagraphWriter {
-    fn eval_expr(&mut self, node: &typst::ast::Expr, dic: &Dictionary) -> FromTypstResult<()> {
-        match node {
+    fn image_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let mut path = None;
+        let mut alt = None;
+        for arg in args.items() {
+            match arg {
+                typst::ast::Arg::Pos(typst::ast::Expr::Str(s)) if path.is_none() => {
+                    path = Some(s.get().to_string());
+                }
+                typst::ast::Arg::Pos(extra) => {
+                    return Err(FromTypstError::invalid_arg(
+                        "image",
+                        "path must be a string".to_string(),
+                    )
+                    .or_span(extra.span()))
+                }
+                typst::ast::Arg::Named(named) => match named.name().get().as_str() {
+                    "alt" => match named.expr() {
+                        typst::ast::Expr::Str(s) => alt = Some(s.get().to_string()),
+                        _ => {
+                            return Err(FromTypstError::invalid_arg(
+                                "image",
+                                "`alt` must be a string".to_string(),
+                            )
+                            .or_span(named.expr().span()))
+                        }
+                    },
+                    _ if ignore_appearance_arg("image", &named, ctx) => {}
+                    name => {
+                        return Err(
+                            FromTypstError::unknown_named_arg("image", name).or_span(named.span())
+                        )
+                    }
+                },
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(
+                        FromTypstError::spread_arg_unsupported("image").or_span(spread.span())
+                    )
+                }
+            }
+        }
+        let Some(path) = path else {
+            return Err(FromTypstError::missing_arg("image", "path"));
+        };
+        self.push_segment(mathlog::Segment::Image(mathlog::Image { path, alt }));
+        Ok(())
+    }
+
+    /// `mat`, `vec` and `cases`: rows are separated by `;` in `mat` and by `,` otherwise.
+    fn math_matrix(
+        &mut self,
+        func: &str,
+        args: &typst::ast::Args,
+        ctx: &Context,
+    ) -> FromTypstResult<()> {
+        let cell = |expr: &typst::ast::Expr| -> FromTypstResult<mathlog::Segments> {
+            let mut writer = SegmentWriter::new();
+            writer.expr(expr, ctx)?;
+            let mut content = writer.export();
+            // alignment points cannot be expressed in a cell of a matrix
+            if func == "mat" {
+                content
+                    .0
+                    .retain(|segment| !matches!(segment, mathlog::Segment::MathAlignPoint));
+            }
+            Ok(content)
+        };
+
+        let mut delim = Some(if func == "cases" { "{" } else { "(" }.to_string());
+        let mut augment = (vec![], vec![]);
+        let mut reverse = false;
+        let mut rows = vec![];
+        let mut last_row = vec![];
+        for arg in args.items() {
+            match arg {
+                typst::ast::Arg::Pos(typst::ast::Expr::Array(array)) if func == "mat" => {
+                    let row = array
+                        .items()
+                        .map(|item| match item {
+                            typst::ast::ArrayItem::Pos(expr) => cell(&expr),
+                            typst::ast::ArrayItem::Spread(spread) => {
+                                Err(FromTypstError::spread_arg_unsupported(func)
+                                    .or_span(spread.span()))
+                            }
+                        })
+                        .collect::<FromTypstResult<_>>()?;
+                    rows.push(row);
+                }
+                typst::ast::Arg::Pos(expr) if func == "mat" => last_row.push(cell(&expr)?),
+                typst::ast::Arg::Pos(expr) => rows.push(vec![cell(&expr)?]),
+                typst::ast::Arg::Named(named) => match named.name().get().as_str() {
+                    "delim" => {
+                        delim = match named.expr() {
+                            typst::ast::Expr::Str(s) => Some(s.get().to_string()),
+                            typst::ast::Expr::None(_) => None,
+                            _ => {
+                                return Err(FromTypstError::invalid_arg(
+                                    func,
+                                    "`delim` must be a string or `none`".to_string(),
+                                )
+                                .or_span(named.expr().span()))
+                            }
+                        }
+                    }
+                    "augment" if func == "mat" => augment = matrix_augment(&named.expr(), ctx)?,
+                    "reverse" if func == "cases" => {
+                        reverse = matches!(named.expr(), typst::ast::Expr::Bool(b) if b.get());
+                    }
+                    _ if ignore_appearance_arg(func, &named, ctx) => {}
+                    name => {
+                        return Err(
+                            FromTypstError::unknown_named_arg(func, name).or_span(named.span())
+                        )
+                    }
+                },
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(FromTypstError::spread_arg_unsupported(func).or_span(spread.span()))
+                }
+            }
+        }
+        if !last_row.is_empty() {
+            rows.push(last_row);
+        }
+
+        let Some((env, open, close)) = matrix_delims(delim.as_deref()) else {
+            return Err(FromTypstError::invalid_arg(
+                func,
+                format!("unsupported delimiter {:?}", delim.unwrap_or_default()),
+            ));
+        };
+        let (hlines, vlines) = augment;
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let resolve_line = |i: i64, len: usize| {
+            if i < 0 {
+                (len as i64 + i) as usize
+            } else {
+                i as usize
+            }
+        };
+
+        let math_env = if func == "cases" {
+            let (name, open, close) = match (delim.as_deref(), reverse) {
+                (Some("{"), false) => ("cases", None, None),
+                (Some("{"), true) => ("rcases", None, None),
+                (_, false) => ("array", Some(open), Some(".")),
+                (_, true) => ("array", Some("."), Some(close)),
+            };
+            let math_env = mathlog::MathEnv {
+                name: name.to_string(),
+                column_spec: (name == "array").then(|| "ll".to_string()),
+                rows,
+                hlines: vec![],
+            };
+            match (open, close) {
+                (Some(open), Some(close)) => delimited(open, math_env, close),
+                _ => mathlog::Segment::MathEnv(math_env),
+            }
+        } else if hlines.is_empty() && vlines.is_empty() {
+            mathlog::Segment::MathEnv(mathlog::MathEnv {
+                name: env.to_string(),
+                column_spec: None,
+                rows,
+                hlines: vec![],
+            })
+        } else {
+            // augmented matrices need the column specification of `array`
+            let vlines = vlines
+                .into_iter()
+                .map(|i| resolve_line(i, columns))
+                .collect::<Vec<_>>();
+            let column_spec = (0..columns)
+                .map(|j| if vlines.contains(&j) { "|c" } else { "c" })
+                .collect::<String>();
+            let hlines = hlines
+                .into_iter()
+                .map(|i| resolve_line(i, rows.len()))
+                .collect();
+            let math_env = mathlog::MathEnv {
+                name: "array".to_string(),
+                column_spec: Some(column_spec),
+                rows,
+                hlines,
+            };
+            delimited(open, math_env, close)
+        };
+        self.push_segment(math_env);
+        Ok(())
+    }
+
+    fn math_binom(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let mut contents = vec![];
+        for arg in args.items() {
+            match arg {
+                typst::ast::Arg::Pos(expr) => {
+                    let mut writer = SegmentWriter::new();
+                    writer.expr(&expr, ctx)?;
+                    contents.push(writer.export());
+                }
+                typst::ast::Arg::Named(named) => {
+                    return Err(
+                        FromTypstError::unknown_named_arg("binom", named.name().get())
+                            .or_span(named.span()),
+                    )
+                }
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(
+                        FromTypstError::spread_arg_unsupported("binom").or_span(spread.span())
+                    )
+                }
+            }
+        }
+        if contents.len() < 2 {
+            return Err(FromTypstError::missing_arg("binom", "lower"));
+        }
+        let upper = contents.remove(0);
+        // a multinomial `binom(n, k_1, k_2)` has the lower indices separated by commas
+        let mut lower = mathlog::Segments::new();
+        for (i, content) in contents.into_iter().enumerate() {
+            if i != 0 {
+                lower.push(mathlog::Segment::Text(mathlog::Text(",".to_string())));
+            }
+            lower.0.extend(content.0);
+        }
+        self.push_segment(mathlog::Segment::Command(mathlog::Command {
+            name: "binom".to_string(),
+            args: vec![
+                mathlog::Arg {
+                    is_optional: false,
+                    content: upper,
+                },
+                mathlog::Arg {
+                    is_optional: false,
+                    content: lower,
+                },
+            ],
+        }));
+        Ok(())
+    }
+
+    /// Accent functions such as `hat(x)`, which become `\widehat` and so on on a wide base.
+    fn accent(
+        &mut self,
+        func: &str,
+        args: &typst::ast::Args,
+        ctx: &Context,
+    ) -> FromTypstResult<()> {
+        let [base] = positional_args(func, args, ["base"], |named| {
+            Ok(named.name().get() == "size")
+        })?;
+        let accent = accent_by_name(func).unwrap();
+        self.push_accent(accent, &base, ctx)
+    }
+
+    /// `accent(base, accent)`, where the accent is resolved through the dictionary
+    /// unless it is the name of an accent function itself.
+    fn accent_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let [base, accent] = positional_args("accent", args, ["base", "accent"], |named| {
+            Ok(named.name().get() == "size")
+        })?;
+
+        let by_name = match &accent {
+            typst::ast::Expr::MathIdent(ident) => accent_by_name(ident.get()),
+            typst::ast::Expr::FieldAccess(access) => {
+                math_field_path(access).and_then(|name| accent_by_name(&name))
+            }
+            _ => None,
+        };
+        let accent = match (by_name, &accent) {
+            (Some(accent), _) => accent,
+            (None, typst::ast::Expr::Str(s)) => {
+                accent_by_symbol(s.get().as_str()).ok_or_else(|| {
+                    FromTypstError::invalid_arg(
+                        "accent",
+                        format!("unsupported accent {:?}", s.get()),
+                    )
+                    .or_span(s.span())
+                })?
+            }
+            (None, _) => {
+                let mut writer = SegmentWriter::new();
+                writer.expr(&accent, ctx)?;
+                let symbol = match &writer.export().0[..] {
+                    [mathlog::Segment::Text(mathlog::Text(s))]
+                    | [mathlog::Segment::RawCommand(mathlog::RawCommand(s))] => s.clone(),
+                    _ => String::new(),
+                };
+                accent_by_symbol(&symbol).ok_or_else(|| {
+                    FromTypstError::invalid_arg(
+                        "accent",
+                        format!("unsupported accent {:?}", symbol),
+                    )
+                    .or_span(accent.span())
+                })?
+            }
+        };
+        self.push_accent(accent, &base, ctx)
+    }
+
+    fn push_accent(
+        &mut self,
+        accent: &Accent,
+        base: &typst::ast::Expr,
+        ctx: &Context,
+    ) -> FromTypstResult<()> {
+        let mut writer = SegmentWriter::new();
+        writer.expr(base, ctx)?;
+        let content = writer.export();
+        // a single letter or symbol takes the narrow accent
+        let narrow = match &content.0[..] {
+            [mathlog::Segment::Text(mathlog::Text(s))] => s.chars().count() <= 1,
+            [mathlog::Segment::RawCommand(_)] => true,
+            _ => false,
+        };
+        let name = match accent.wide {
+            Some(wide) if !narrow => wide,
+            _ => accent.command,
+        };
+        self.push_segment(mathlog::Segment::Command(mathlog::Command {
+            name: name.to_string(),
+            args: vec![mathlog::Arg {
+                is_optional: false,
+                content,
+            }],
+        }));
+        Ok(())
+    }
+
+    /// `overbrace(body, annotation)` and the like, where the annotation is attached as a script.
+    fn math_brace(
+        &mut self,
+        func: &str,
+        args: &typst::ast::Args,
+        ctx: &Context,
+    ) -> FromTypstResult<()> {
+        let mut contents = vec![];
+        for arg in args.items() {
+            match arg {
+                typst::ast::Arg::Pos(expr) => {
+                    let mut writer = SegmentWriter::new();
+                    writer.expr(&expr, ctx)?;
+                    contents.push(writer.export());
+                }
+                typst::ast::Arg::Named(named) => {
+                    return Err(FromTypstError::unknown_named_arg(func, named.name().get())
+                        .or_span(named.span()))
+                }
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(FromTypstError::spread_arg_unsupported(func).or_span(spread.span()))
+                }
+            }
+        }
+        let mut contents = contents.into_iter();
+        let body = contents
+            .next()
+            .ok_or_else(|| FromTypstError::missing_arg(func, "body"))?;
+        let annotation = contents.next();
+        if contents.next().is_some() {
+            return Err(FromTypstError::invalid_arg(
+                func,
+                "too many arguments".to_string(),
+            ));
+        }
+
+        let command = mathlog::Segment::Command(mathlog::Command {
+            name: func.to_string(),
+            args: vec![mathlog::Arg {
+                is_optional: false,
+                content: body,
+            }],
+        });
+        let segment = match annotation {
+            Some(annotation) => {
+                let over = func.starts_with("over");
+                mathlog::Segment::MathAttach(mathlog::MathAttach {
+                    base: mathlog::Segments(vec![command]),
+                    top: over.then(|| annotation.clone()),
+                    bottom: (!over).then_some(annotation),
+                    top_left: None,
+                    bottom_left: None,
+                    primes: 0,
+                    limits: None,
+                })
+            }
+            None => command,
+        };
+        self.push_segment(segment);
+        Ok(())
+    }
+
+    /// `cancel`, `cancel(inverted: true)` and `cancel(cross: true)` are
+    /// `\cancel`, `\bcancel` and `\xcancel` respectively.
+    fn math_cancel(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let mut inverted = false;
+        let mut cross = false;
+        let [body] = positional_args("cancel", args, ["body"], |named| {
+            let flag = matches!(named.expr(), typst::ast::Expr::Bool(b) if b.get());
+            match named.name().get().as_str() {
+                "inverted" => inverted = flag,
+                "cross" => cross = flag,
+                _ => return Ok(ignore_appearance_arg("cancel", &named, ctx)),
+            }
+            Ok(true)
+        })?;
+
+        let mut writer = SegmentWriter::new();
+        writer.expr(&body, ctx)?;
+        let name = match (cross, inverted) {
+            (true, _) => "xcancel",
+            (false, true) => "bcancel",
+            (false, false) => "cancel",
+        };
+        self.push_segment(mathlog::Segment::Command(mathlog::Command {
+            name: name.to_string(),
+            args: vec![mathlog::Arg {
+                is_optional: false,
+                content: writer.export(),
+            }],
+        }));
+        Ok(())
+    }
+
+    /// Functions in [`SINGLE_CALLS`], which take only a body.
+    fn single_call(
+        &mut self,
+        func: &str,
+        args: &typst::ast::Args,
+        ctx: &Context,
+    ) -> FromTypstResult<()> {
+        let name = single_call_command(func).unwrap();
+        let [body] = positional_args(func, args, ["body"], |_| Ok(false))?;
+
+        let mut writer = SegmentWriter::new();
+        writer.expr(&body, ctx)?;
+        let content = writer.export();
+        self.push_segment(mathlog::Segment::Command(mathlog::Command {
+            name: name.to_string(),
+            args: vec![mathlog::Arg {
+                is_optional: false,
+                content,
+            }],
+        }));
+        Ok(())
+    }
+
+    /// Nested functions in [`STYLE_FUNCS`] such as `bold(upright(x))`, combined into
+    /// a single style where inner functions take precedence.
+    fn style_call(
+        &mut self,
+        func: &str,
+        args: &typst::ast::Args,
+        ctx: &Context,
+    ) -> FromTypstResult<()> {
+        let mut variant = None;
+        let mut upright = None;
+        let mut bold = false;
+        let mut func = func.to_string();
+        let mut args = *args;
+        let body = loop {
+            match style_change(&func).unwrap() {
+                StyleChange::Variant(command) => variant = Some(command),
+                StyleChange::Upright(value) => upright = Some(value),
+                StyleChange::Bold => bold = true,
+            }
+            let [body] = positional_args(&func, &args, ["body"], |_| Ok(false))?;
+            match body {
+                typst::ast::Expr::FuncCall(call) => match call.callee() {
+                    typst::ast::Expr::MathIdent(ident) if style_change(ident.get()).is_some() => {
+                        func = ident.get().to_string();
+                        args = call.args();
+                    }
+                    _ => break body,
+                },
+                _ => break body,
+            }
+        };
+
+        let command = match (variant.flatten(), upright) {
+            (Some(command), _) => Some(command),
+            (None, Some(true)) if bold => {
+                bold = false;
+                Some("mathbf")
+            }
+            (None, Some(true)) => Some("mathrm"),
+            (None, Some(false)) => Some("mathit"),
+            (None, None) => None,
+        };
+
+        let mut writer = SegmentWriter::new();
+        writer.expr(&body, ctx)?;
+        let mut content = writer.export();
+        for name in command.into_iter().chain(bold.then_some("boldsymbol")) {
+            content = mathlog::Segments(vec![mathlog::Segment::Command(mathlog::Command {
+                name: name.to_string(),
+                args: vec![mathlog::Arg {
+                    is_optional: false,
+                    content,
+                }],
+            })]);
+        }
+        for segment in content.0 {
+            self.push_segment(segment);
+        }
+        Ok(())
+    }
+
+    /// Functions in [`SIZE_FUNCS`] as `{\displaystyle ...}` and so on.
+    fn size_call(
+        &mut self,
+        func: &str,
+        args: &typst::ast::Args,
+        ctx: &Context,
+    ) -> FromTypstResult<()> {
+        let (_, name) = SIZE_FUNCS.iter().find(|(typst, _)| *typst == func).unwrap();
+        let [body] = positional_args(func, args, ["body"], |named| {
+            Ok(named.name().get() == "cramped")
+        })?;
+
+        let mut writer = SegmentWriter::new();
+        writer.expr(&body, ctx)?;
+        self.push_segment(mathlog::Segment::RawCommand(mathlog::RawCommand(format!(
+            "{{\\{}",
+            name
+        ))));
+        for segment in writer.export().0 {
+            self.push_segment(segment);
+        }
+        self.push_segment(mathlog::Segment::RawCommand(mathlog::RawCommand(
+            "}".to_string(),
+        )));
+        Ok(())
+    }
+
+    /// `root(index, radicand)`, the function form of `√`.
+    fn root_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let [index, radicand] =
+            positional_args("root", args, ["index", "radicand"], |_| Ok(false))?;
+        let mut index_writer = SegmentWriter::new();
+        index_writer.expr(&index, ctx)?;
+        let mut radicand_writer = SegmentWriter::new();
+        radicand_writer.expr(&radicand, ctx)?;
+        self.push_segment(mathlog::Segment::Command(mathlog::Command {
+            name: "sqrt".to_string(),
+            args: vec![
+                mathlog::Arg {
+                    is_optional: true,
+                    content: index_writer.export(),
+                },
+                mathlog::Arg {
+                    is_optional: false,
+                    content: radicand_writer.export(),
+                },
+            ],
+        }));
+        Ok(())
+    }
+
+    /// `frac(num, denom)`, the function form of `/`.
+    fn frac_call(&mut self, args: &typst::ast::Args, ctx: &Context) -> FromTypstResult<()> {
+        let [num, denom] = positional_args("frac", args, ["num", "denom"], |_| Ok(false))?;
+        let mut num_writer = SegmentWriter::new();
+        num_writer.expr(&num, ctx)?;
+        let mut denom_writer = SegmentWriter::new();
+        denom_writer.expr(&denom, ctx)?;
+        self.push_segment(mathlog::Segment::Command(mathlog::Command {
+            name: "frac".to_string(),
+            args: vec![
+                mathlog::Arg {
+                    is_optional: false,
+                    content: num_writer.export(),
+                },
+                mathlog::Arg {
+                    is_optional: false,
+                    content: denom_writer.export(),
+                },
+            ],
+        }));
+        Ok(())
+    }
+}
+
+impl SegmentWriter {
+    fn eval_expr(&mut self, node: &typst::ast::Expr, ctx: &Context) -> FromTypstResult<()> {
+        let result = match node {
             typst::ast::Expr::Str(s) => {
                 let s = s.get().to_string();
                 self.push_segment(mathlog::Segment::Text(mathlog::Text(s)));
@@ -978,9 +2858,1118 @@ impl ParagraphWriter {
             }
             typst::ast::Expr::Content(content_block) => {
                 let markup = content_block.body();
-                self.markup(&markup, dic)
+                self.markup(&markup, ctx)
+            }
+            typst::ast::Expr::FuncCall(func_call) => self.func_call(func_call, ctx),
+            _ => Err(FromTypstError::not_content(node)),
+        };
+        result.map_err(|err| err.or_span(node.span()))
+    }
+}
+
+/// Math functions taking only a body, and the LaTeX commands for them.
+const SINGLE_CALLS: [(&str, &str); 3] = [
+    ("sqrt", "sqrt"),
+    ("overline", "overline"),
+    ("underline", "underline"),
+];
+
+fn single_call_command(func: &str) -> Option<&'static str> {
+    SINGLE_CALLS
+        .iter()
+        .find(|(typst, _)| *typst == func)
+        .map(|(_, command)| *command)
+}
+
+/// What a style function in math changes.
+#[derive(Debug, Clone, Copy)]
+enum StyleChange {
+    /// The font variant and its command, which is none for `serif`.
+    Variant(Option<&'static str>),
+    /// `upright` or `italic`
+    Upright(bool),
+    Bold,
+}
+
+const STYLE_FUNCS: [(&str, StyleChange); 10] = [
+    ("serif", StyleChange::Variant(None)),
+    ("sans", StyleChange::Variant(Some("mathsf"))),
+    ("mono", StyleChange::Variant(Some("mathtt"))),
+    ("frak", StyleChange::Variant(Some("mathfrak"))),
+    ("fraktur", StyleChange::Variant(Some("mathfrak"))),
+    ("bb", StyleChange::Variant(Some("mathbb"))),
+    ("cal", StyleChange::Variant(Some("mathcal"))),
+    ("upright", StyleChange::Upright(true)),
+    ("italic", StyleChange::Upright(false)),
+    ("bold", StyleChange::Bold),
+];
+
+fn style_change(func: &str) -> Option<StyleChange> {
+    STYLE_FUNCS
+        .iter()
+        .find(|(typst, _)| *typst == func)
+        .map(|(_, change)| *change)
+}
+
+/// Math size functions and the LaTeX style switches for them.
+const SIZE_FUNCS: [(&str, &str); 4] = [
+    ("display", "displaystyle"),
+    ("inline", "textstyle"),
+    ("script", "scriptstyle"),
+    ("sscript", "scriptscriptstyle"),
+];
+
+/// Named arguments which only affect the appearance, and so are dropped.
+/// `augment` stands for the dictionary given to `augment` of `mat`.
+const APPEARANCE_ARGS: [(&str, &[&str]); 13] = [
+    (
+        "enum",
+        &[
+            "tight",
+            "full",
+            "indent",
+            "body-indent",
+            "spacing",
+            "number-align",
+        ],
+    ),
+    ("figure", &["placement", "gap", "outlined"]),
+    ("table", &TABLE_APPEARANCE_ARGS),
+    ("grid", &TABLE_APPEARANCE_ARGS),
+    ("footnote", &["numbering"]),
+    ("image", &["width", "height", "fit", "format"]),
+    ("mat", &MATRIX_APPEARANCE_ARGS),
+    ("vec", &MATRIX_APPEARANCE_ARGS),
+    ("cases", &MATRIX_APPEARANCE_ARGS),
+    ("augment", &["stroke"]),
+    ("cancel", &["length", "angle", "stroke"]),
+    // `inline` only matters in inline math
+    ("limits", &["inline"]),
+    ("scripts", &["inline"]),
+];
+
+const TABLE_APPEARANCE_ARGS: [&str; 7] = [
+    "rows",
+    "gutter",
+    "column-gutter",
+    "row-gutter",
+    "fill",
+    "stroke",
+    "inset",
+];
+
+const MATRIX_APPEARANCE_ARGS: [&str; 4] = ["gap", "row-gap", "column-gap", "align"];
+
+/// Whether `named` is an argument of `func` which only affects the appearance, warned about as
+/// ignored if so.
+fn ignore_appearance_arg(func: &str, named: &typst::ast::Named, ctx: &Context) -> bool {
+    let name = named.name().get();
+    let ignored = APPEARANCE_ARGS
+        .iter()
+        .any(|(f, names)| *f == func && names.contains(&name.as_str()));
+    if ignored {
+        ctx.warn(
+            FromTypstWarningKind::ArgIgnored(func.to_string(), name.to_string()),
+            named.span(),
+        );
+    }
+    ignored
+}
+
+/// The positional arguments of `func` named `names`, which must all be given.
+/// `named` takes each named argument, and returns `false` for an unknown one.
+fn positional_args<'a, const N: usize>(
+    func: &str,
+    args: &typst::ast::Args<'a>,
+    names: [&str; N],
+    mut named: impl FnMut(typst::ast::Named<'a>) -> FromTypstResult<bool>,
+) -> FromTypstResult<[typst::ast::Expr<'a>; N]> {
+    let mut pos = vec![];
+    for arg in args.items() {
+        match arg {
+            typst::ast::Arg::Pos(expr) => pos.push(expr),
+            typst::ast::Arg::Named(arg) => {
+                if !named(arg)? {
+                    return Err(FromTypstError::unknown_named_arg(func, arg.name().get())
+                        .or_span(arg.span()));
+                }
+            }
+            typst::ast::Arg::Spread(spread) => {
+                return Err(FromTypstError::spread_arg_unsupported(func).or_span(spread.span()))
+            }
+        }
+    }
+    if pos.len() > N {
+        return Err(
+            FromTypstError::invalid_arg(func, "too many arguments".to_string())
+                .or_span(pos[N].span()),
+        );
+    }
+    let len = pos.len();
+    pos.try_into()
+        .map_err(|_| FromTypstError::missing_arg(func, names[len]))
+}
+
+/// An accent in math: the Typst function name, the characters it puts on the base,
+/// and the LaTeX commands for a single letter and for a wider base.
+struct Accent {
+    name: &'static str,
+    symbols: &'static [&'static str],
+    command: &'static str,
+    wide: Option<&'static str>,
+}
+
+const ACCENTS: [Accent; 16] = [
+    Accent {
+        name: "grave",
+        symbols: &["`", "\u{300}"],
+        command: "grave",
+        wide: None,
+    },
+    Accent {
+        name: "acute",
+        symbols: &["´", "\\acute", "\u{301}"],
+        command: "acute",
+        wide: None,
+    },
+    Accent {
+        name: "hat",
+        symbols: &["^", "ˆ", "\u{302}"],
+        command: "hat",
+        wide: Some("widehat"),
+    },
+    Accent {
+        name: "tilde",
+        symbols: &["~", "∼", "˜", "\u{303}"],
+        command: "tilde",
+        wide: Some("widetilde"),
+    },
+    Accent {
+        name: "macron",
+        symbols: &["¯", "\u{304}"],
+        command: "bar",
+        wide: Some("overline"),
+    },
+    Accent {
+        name: "breve",
+        symbols: &["˘", "\u{306}"],
+        command: "breve",
+        wide: None,
+    },
+    // the dictionary maps the symbol `dot` to `\cdot`
+    Accent {
+        name: "dot",
+        symbols: &["˙", "⋅", "\\cdot", "\u{307}"],
+        command: "dot",
+        wide: None,
+    },
+    Accent {
+        name: "dot.double",
+        symbols: &["¨", "\u{308}"],
+        command: "ddot",
+        wide: None,
+    },
+    Accent {
+        name: "diaer",
+        symbols: &[],
+        command: "ddot",
+        wide: None,
+    },
+    Accent {
+        name: "dot.triple",
+        symbols: &["\u{20db}"],
+        command: "dddot",
+        wide: None,
+    },
+    Accent {
+        name: "dot.quad",
+        symbols: &["\u{20dc}"],
+        command: "ddddot",
+        wide: None,
+    },
+    Accent {
+        name: "circle",
+        symbols: &["∘", "○", "˚", "\u{30a}"],
+        command: "mathring",
+        wide: None,
+    },
+    Accent {
+        name: "caron",
+        symbols: &["ˇ", "\u{30c}"],
+        command: "check",
+        wide: None,
+    },
+    Accent {
+        name: "arrow",
+        symbols: &["→", "\u{20d7}"],
+        command: "vec",
+        wide: Some("overrightarrow"),
+    },
+    Accent {
+        name: "arrow.l",
+        symbols: &["←", "\u{20d6}"],
+        command: "overleftarrow",
+        wide: None,
+    },
+    Accent {
+        name: "arrow.l.r",
+        symbols: &["↔", "\u{20e1}"],
+        command: "overleftrightarrow",
+        wide: None,
+    },
+];
+
+fn accent_by_name(name: &str) -> Option<&'static Accent> {
+    ACCENTS.iter().find(|accent| accent.name == name)
+}
+
+fn accent_by_symbol(symbol: &str) -> Option<&'static Accent> {
+    ACCENTS
+        .iter()
+        .find(|accent| accent.symbols.contains(&symbol))
+}
+
+/// The dotted path of a field access in math such as `dot.double`.
+fn math_field_path(node: &typst::ast::FieldAccess) -> Option<String> {
+    let target = match node.target() {
+        typst::ast::Expr::MathIdent(ident) => ident.get().to_string(),
+        typst::ast::Expr::FieldAccess(access) => math_field_path(&access)?,
+        _ => return None,
+    };
+    Some(format!("{}.{}", target, node.field().get()))
+}
+
+/// The base of an attachment, and whether `limits` or `scripts` is applied to it.
+fn attach_base(
+    base: &typst::ast::Expr,
+    ctx: &Context,
+) -> FromTypstResult<(mathlog::Segments, Option<bool>)> {
+    match base {
+        typst::ast::Expr::FuncCall(call) => match call.callee() {
+            typst::ast::Expr::MathIdent(ident) if ident.get() == "limits" => Ok((
+                as_operator(limits_base(ident.get(), &call.args(), ctx)?),
+                Some(true),
+            )),
+            typst::ast::Expr::MathIdent(ident) if ident.get() == "scripts" => Ok((
+                as_operator(limits_base(ident.get(), &call.args(), ctx)?),
+                Some(false),
+            )),
+            _ => {
+                let mut writer = SegmentWriter::new();
+                writer.expr(base, ctx)?;
+                Ok((writer.export(), None))
+            }
+        },
+        // e.g. `"supp"_R`, which is an operator rather than a word
+        typst::ast::Expr::Str(s) if is_operator_name(&s.get()) => Ok((
+            mathlog::Segments(vec![operator_name(
+                mathlog::Segments(vec![mathlog::Segment::Text(mathlog::Text(
+                    s.get().to_string(),
+                ))]),
+                false,
+            )]),
+            None,
+        )),
+        _ => {
+            let mut writer = SegmentWriter::new();
+            writer.expr(base, ctx)?;
+            Ok((writer.export(), None))
+        }
+    }
+}
+
+/// Whether a string in math reads as the name of an operator such as `"supp"`.
+fn is_operator_name(s: &str) -> bool {
+    s.chars().count() >= 2 && s.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+fn operator_name(content: mathlog::Segments, limits: bool) -> mathlog::Segment {
+    mathlog::Segment::Command(mathlog::Command {
+        name: if limits {
+            "operatorname*"
+        } else {
+            "operatorname"
+        }
+        .to_string(),
+        args: vec![mathlog::Arg {
+            is_optional: false,
+            content,
+        }],
+    })
+}
+
+/// The body of `limits` or `scripts`.
+fn limits_base(
+    func: &str,
+    args: &typst::ast::Args,
+    ctx: &Context,
+) -> FromTypstResult<mathlog::Segments> {
+    let [body] = positional_args(func, args, ["body"], |named| {
+        Ok(ignore_appearance_arg(func, &named, ctx))
+    })?;
+
+    let mut writer = SegmentWriter::new();
+    match &body {
+        typst::ast::Expr::Str(s) if is_operator_name(&s.get()) => {
+            return Ok(mathlog::Segments(vec![operator_name(
+                mathlog::Segments(vec![mathlog::Segment::Text(mathlog::Text(
+                    s.get().to_string(),
+                ))]),
+                false,
+            )]))
+        }
+        _ => writer.expr(&body, ctx)?,
+    }
+    Ok(writer.export())
+}
+
+/// `\limits` needs an operator before it, so anything other than a symbol or
+/// an operator name is made into one by `\mathop`.
+fn as_operator(content: mathlog::Segments) -> mathlog::Segments {
+    match &content.0[..] {
+        [mathlog::Segment::RawCommand(_)] => content,
+        [mathlog::Segment::Command(command)] if command.name.starts_with("operatorname") => content,
+        _ => mathlog::Segments(vec![mathlog::Segment::Command(mathlog::Command {
+            name: "mathop".to_string(),
+            args: vec![mathlog::Arg {
+                is_optional: false,
+                content,
+            }],
+        })]),
+    }
+}
+
+/// Whether `node` can be scaled by `lr`: a symbol rather than a letter or a number.
+fn is_delimiter(node: &typst::ast::Expr) -> bool {
+    match node {
+        typst::ast::Expr::Text(text) => !text.get().chars().any(char::is_alphanumeric),
+        typst::ast::Expr::Shorthand(_)
+        | typst::ast::Expr::MathIdent(_)
+        | typst::ast::Expr::FieldAccess(_) => true,
+        _ => false,
+    }
+}
+
+/// The fixed size of delimiters closest to `size` of `lr`, where `100%` fits them to the body.
+/// `\big`, `\Big`, `\bigg` and `\Bigg` are about 1.2, 1.8, 2.4 and 3 times as large as normal.
+fn delimiter_size(func: &str, node: &typst::ast::Expr) -> FromTypstResult<mathlog::DelimiterSize> {
+    let factor = match node {
+        typst::ast::Expr::Numeric(numeric) => match numeric.get() {
+            (100.0, typst::ast::Unit::Percent) => return Ok(mathlog::DelimiterSize::Auto),
+            (value, typst::ast::Unit::Percent) => value / 100.0,
+            (value, typst::ast::Unit::Em) => value,
+            _ => {
+                return Err(FromTypstError::invalid_arg(
+                    func,
+                    "`size` must be in `em` or `%`".to_string(),
+                )
+                .or_span(node.span()))
+            }
+        },
+        typst::ast::Expr::Auto(_) => return Ok(mathlog::DelimiterSize::Auto),
+        _ => {
+            return Err(FromTypstError::invalid_arg(
+                func,
+                "`size` must be in `em` or `%`".to_string(),
+            )
+            .or_span(node.span()))
+        }
+    };
+    Ok(if factor <= 1.0 {
+        mathlog::DelimiterSize::Normal
+    } else if factor <= 1.2 {
+        mathlog::DelimiterSize::Big
+    } else if factor <= 1.8 {
+        mathlog::DelimiterSize::BigCap
+    } else if factor <= 2.4 {
+        mathlog::DelimiterSize::Bigg
+    } else {
+        mathlog::DelimiterSize::BiggCap
+    })
+}
+
+/// The matrix environment and the delimiters for `\left`/`\right` for a `delim` of `mat`.
+fn matrix_delims(delim: Option<&str>) -> Option<(&'static str, &'static str, &'static str)> {
+    match delim {
+        Some("(") => Some(("pmatrix", "(", ")")),
+        Some("[") => Some(("bmatrix", "[", "]")),
+        Some("{") => Some(("Bmatrix", "\\{", "\\}")),
+        Some("|") => Some(("vmatrix", "|", "|")),
+        Some("||" | "‖") => Some(("Vmatrix", "\\|", "\\|")),
+        None => Some(("matrix", ".", ".")),
+        _ => None,
+    }
+}
+
+fn delimited(open: &str, math_env: mathlog::MathEnv, close: &str) -> mathlog::Segment {
+    let delim = |s: &str| {
+        mathlog::Segments(vec![mathlog::Segment::RawCommand(mathlog::RawCommand(
+            s.to_string(),
+        ))])
+    };
+    mathlog::Segment::MathDelimited(mathlog::MathDelimited {
+        open: delim(open),
+        body: mathlog::Segments(vec![mathlog::Segment::MathEnv(math_env)]),
+        close: delim(close),
+        size: mathlog::DelimiterSize::Auto,
+    })
+}
+
+/// The horizontal and vertical lines given by `augment` of `mat`.
+/// Negative indices count from the end.
+fn matrix_augment(node: &typst::ast::Expr, ctx: &Context) -> FromTypstResult<(Vec<i64>, Vec<i64>)> {
+    fn lines(node: &typst::ast::Expr) -> FromTypstResult<Vec<i64>> {
+        match node {
+            typst::ast::Expr::Int(int) => Ok(vec![int.get()]),
+            typst::ast::Expr::Unary(unary) => match (unary.op(), unary.expr()) {
+                (typst::ast::UnOp::Neg, typst::ast::Expr::Int(int)) => Ok(vec![-int.get()]),
+                _ => Err(invalid()),
+            },
+            typst::ast::Expr::Array(array) => array
+                .items()
+                .map(|item| match item {
+                    typst::ast::ArrayItem::Pos(expr) => lines(&expr)?.pop().ok_or_else(invalid),
+                    typst::ast::ArrayItem::Spread(_) => Err(invalid()),
+                })
+                .collect(),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn invalid() -> FromTypstError {
+        FromTypstError::invalid_arg(
+            "mat",
+            "`augment` must be an integer, or a dictionary of `hline` and `vline`".to_string(),
+        )
+    }
+
+    match node {
+        typst::ast::Expr::Dict(dict) => {
+            let mut augment = (vec![], vec![]);
+            for item in dict.items() {
+                match item {
+                    typst::ast::DictItem::Named(named) => match named.name().get().as_str() {
+                        "hline" => augment.0 = lines(&named.expr())?,
+                        "vline" => augment.1 = lines(&named.expr())?,
+                        _ if ignore_appearance_arg("augment", &named, ctx) => {}
+                        _ => return Err(invalid()),
+                    },
+                    _ => return Err(invalid()),
+                }
+            }
+            Ok(augment)
+        }
+        _ => Ok((vec![], lines(node)?)),
+    }
+}
+
+fn numbering_arg(func: &str, named: &typst::ast::Named) -> FromTypstResult<String> {
+    match named.expr() {
+        typst::ast::Expr::Str(s) => Ok(s.get().to_string()),
+        _ => Err(FromTypstError::invalid_arg(
+            func,
+            "`numbering` must be a pattern string".to_string(),
+        )
+        .or_span(named.expr().span())),
+    }
+}
+
+struct QuoteArgs<'a> {
+    block: bool,
+    quotes: Option<bool>,
+    attribution: Option<mathlog::Segments>,
+    body: typst::ast::Expr<'a>,
+}
+
+impl<'a> QuoteArgs<'a> {
+    fn new(args: &typst::ast::Args<'a>, ctx: &Context) -> FromTypstResult<Self> {
+        fn bool_arg(named: &typst::ast::Named) -> FromTypstResult<Option<bool>> {
+            match named.expr() {
+                typst::ast::Expr::Bool(b) => Ok(Some(b.get())),
+                typst::ast::Expr::Auto(_) => Ok(None),
+                _ => Err(FromTypstError::invalid_arg(
+                    "quote",
+                    format!("`{}` must be a boolean", named.name().get()),
+                )
+                .or_span(named.expr().span())),
+            }
+        }
+
+        let mut block = false;
+        let mut quotes = None;
+        let mut attribution = None;
+        let mut body = None;
+        for arg in args.items() {
+            match arg {
+                typst::ast::Arg::Pos(expr) => body = Some(expr),
+                typst::ast::Arg::Named(named) => match named.name().get().as_str() {
+                    "block" => block = bool_arg(&named)?.unwrap_or(false),
+                    "quotes" => quotes = bool_arg(&named)?,
+                    "attribution" => {
+                        let mut writer = SegmentWriter::new();
+                        writer.eval_expr(&named.expr(), ctx)?;
+                        attribution = Some(writer.export());
+                    }
+                    name => {
+                        return Err(
+                            FromTypstError::unknown_named_arg("quote", name).or_span(named.span())
+                        )
+                    }
+                },
+                typst::ast::Arg::Spread(spread) => {
+                    return Err(
+                        FromTypstError::spread_arg_unsupported("quote").or_span(spread.span())
+                    )
+                }
             }
-            _ => todo!("{:?}", node),
         }
+        let Some(body) = body else {
+            return Err(FromTypstError::missing_arg("quote", "body"));
+        };
+        Ok(Self {
+            block,
+            quotes,
+            attribution,
+            body,
+        })
+    }
+}
+
+/// The field name of a call such as `table.cell(..)` on the module `module`.
+fn field_call_name<'a>(node: &typst::ast::FuncCall<'a>, module: &str) -> Option<&'a str> {
+    match node.callee() {
+        typst::ast::Expr::FieldAccess(access) => match access.target() {
+            typst::ast::Expr::Ident(ident) if ident.get() == module => {
+                Some(access.field().get().as_str())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn table_cell(
+    node: &typst::ast::Expr,
+    ctx: &Context,
+    func: &str,
+) -> FromTypstResult<mathlog::Segments> {
+    let mut writer = SegmentWriter::new();
+    match node {
+        typst::ast::Expr::FuncCall(call) if field_call_name(call, func) == Some("cell") => {
+            let mut body = None;
+            for arg in call.args().items() {
+                match arg {
+                    typst::ast::Arg::Pos(expr) => body = Some(expr),
+                    typst::ast::Arg::Named(named) => {
+                        let name = named.name().get().to_string();
+                        let spans = match named.expr() {
+                            typst::ast::Expr::Int(int) => int.get() != 1,
+                            _ => true,
+                        };
+                        if (name == "colspan" || name == "rowspan") && spans {
+                            return Err(FromTypstError::invalid_arg(
+                                func,
+                                format!("`{}` cannot be expressed in a Markdown table", name),
+                            )
+                            .or_span(named.span()));
+                        }
+                    }
+                    typst::ast::Arg::Spread(spread) => {
+                        return Err(
+                            FromTypstError::spread_arg_unsupported(func).or_span(spread.span())
+                        )
+                    }
+                }
+            }
+            let Some(body) = body else {
+                return Err(FromTypstError::missing_arg(func, "body"));
+            };
+            writer.eval_expr(&body, ctx)?;
+        }
+        typst::ast::Expr::FuncCall(call) if field_call_name(call, func).is_some() => {
+            return Err(FromTypstError::unsupported_func_call(vec![
+                func.to_string(),
+                field_call_name(call, func).unwrap_or_default().to_string(),
+            ]))
+        }
+        _ => writer.eval_expr(node, ctx)?,
+    }
+    Ok(writer.export())
+}
+
+fn column_align(node: &typst::ast::Expr, func: &str) -> FromTypstResult<mathlog::ColumnAlign> {
+    match node {
+        typst::ast::Expr::Ident(ident) => match ident.get().as_str() {
+            "left" | "start" => Ok(mathlog::ColumnAlign::Left),
+            "center" => Ok(mathlog::ColumnAlign::Center),
+            "right" | "end" => Ok(mathlog::ColumnAlign::Right),
+            "top" | "horizon" | "bottom" => Ok(mathlog::ColumnAlign::Default),
+            _ => Err(FromTypstError::invalid_arg(
+                func,
+                format!("unknown alignment `{}`", ident.get()),
+            )
+            .or_span(ident.span())),
+        },
+        // e.g. `left + top`
+        typst::ast::Expr::Binary(binary) if binary.op() == typst::ast::BinOp::Add => {
+            let lhs = column_align(&binary.lhs(), func)?;
+            let rhs = column_align(&binary.rhs(), func)?;
+            Ok(if lhs == mathlog::ColumnAlign::Default {
+                rhs
+            } else {
+                lhs
+            })
+        }
+        _ => Err(FromTypstError::invalid_arg(
+            func,
+            "`align` must be an alignment or an array of alignments".to_string(),
+        )
+        .or_span(node.span())),
+    }
+}
+
+impl ParagraphWriter {
+    fn eval_expr(&mut self, node: &typst::ast::Expr, ctx: &Context) -> FromTypstResult<()> {
+        let result = match node {
+            typst::ast::Expr::Str(s) => {
+                let s = s.get().to_string();
+                self.push_segment(mathlog::Segment::Text(mathlog::Text(s)));
+                Ok(())
+            }
+            typst::ast::Expr::Content(content_block) => {
+                let markup = content_block.body();
+                self.markup(&markup, ctx)
+            }
+            typst::ast::Expr::FuncCall(func_call) => self.func_call(func_call, ctx),
+            _ => Err(FromTypstError::not_content(node)),
+        };
+        result.map_err(|err| err.or_span(node.span()))
+    }
+}
+
+/// Converts `source` with the dictionary of the repository.
+#[cfg(test)]
+pub fn convert_source(
+    source: &typst::Source,
+    options: FromTypstOptions,
+) -> FromTypstResult<FromTypstOutput> {
+    let dic_path = concat!(env!("CARGO_MANIFEST_DIR"), "/dictionary/dictionary.json");
+    let dic = Dictionary::read(dic_path).unwrap();
+    let markup = typst::ast::Markup::from_untyped(source.root()).unwrap();
+    mathlog::Syntax::from_typst_with(&markup, &dic, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert_with(src: &str, options: FromTypstOptions) -> FromTypstResult<FromTypstOutput> {
+        convert_source(&typst::Source::detached(src), options)
+    }
+
+    fn convert(src: &str) -> String {
+        convert_with(src, FromTypstOptions::default())
+            .unwrap()
+            .syntax
+            .compose()
+    }
+
+    #[test]
+    fn link() {
+        assert_eq!(
+            convert("参考：https://typst.app，#link(\"https://mathlog.info\")[Mathlog]．"),
+            "参考：<https://typst.app>，[Mathlog](https://mathlog.info)．\n"
+        );
+        assert_eq!(
+            convert("#thm[定理] <thm>\n\n#link(<thm>)[前の定理]，#link(<thm>)"),
+            "&&&thm\n定理\n&&&\n\n前の定理，定理 1\n"
+        );
+        assert_eq!(
+            error_text("#link(<missing>)[導入]"),
+            "link(<missing>)[導入]"
+        );
+    }
+
+    #[test]
+    fn refs() {
+        let src = "#set heading(numbering: \"1.\")\n#set math.equation(numbering: \"(1)\")\n\
+                   = 導入 <intro>\n\n#def[定義]\n\n#lem[補題] <lem>\n\n#thm[定理] <thm>\n\n$ x = 1 $ <eq>\n\n@intro，@thm，@lem[Lemma]，@eq";
+        let output = convert(src);
+        assert!(output.contains("\\tag{1}"));
+        assert!(output.ends_with("節 1，定理 2，Lemma 1，(1)\n"));
+        assert!(matches!(
+            error_kind("= 導入 <intro>\n\n@intro"),
+            FromTypstErrorKind::UnnumberedRef(label) if label == "intro"
+        ));
+        assert!(matches!(
+            error_kind("#axm[公理] <axm>\n\n@axm"),
+            FromTypstErrorKind::UnnumberedRef(label) if label == "axm"
+        ));
+        assert_eq!(
+            convert("= 導入 <intro>\n\n#link(<intro>)[導入へ]"),
+            "# 導入\n\n導入へ\n"
+        );
+    }
+
+    #[test]
+    fn table() {
+        let src =
+            "#table(columns: 2, align: (left, right), table.header[記号][意味], [$x$], [_変数_])";
+        assert_eq!(
+            convert(src),
+            "| 記号 | 意味 |\n| :--- | ---: |\n| $x$ | *変数* |\n"
+        );
+        assert_eq!(
+            convert("#table(columns: 2, [a|b], [$abs(x)$])"),
+            "| a\\|b | $\\left\\|x\\right\\|$ |\n| --- | --- |\n"
+        );
+        assert_eq!(
+            convert("#table(columns: (2), [a], [b])"),
+            "| a | b |\n| --- | --- |\n"
+        );
+        assert_eq!(
+            convert("#table(columns: (1fr), [a], [b])"),
+            "| a |\n| --- |\n| b |\n"
+        );
+        assert!(matches!(
+            error_kind("#table(columns: (), [a])"),
+            FromTypstErrorKind::InvalidArg(..)
+        ));
+        assert!(matches!(
+            error_kind("#table(columns: 0, [a])"),
+            FromTypstErrorKind::InvalidArg(..)
+        ));
+    }
+
+    #[test]
+    fn figure() {
+        let src = "#figure(image(\"a.png\", alt: \"説明\"), caption: [グラフ]) <fig:a>\n\n@fig:a を見よ．";
+        assert_eq!(
+            convert(src),
+            "![説明](a.png)\n\n図 1: グラフ\n\n図 1を見よ．\n"
+        );
+    }
+
+    #[test]
+    fn footnote() {
+        let src = "本文#footnote[$x$ の注．] <fn>．再掲@fn．";
+        assert_eq!(convert(src), "本文[^1]．再掲[^1]．\n\n[^1]: $x$の注．\n");
+        assert_eq!(
+            convert("本文#footnote[注#footnote[注の注]]"),
+            "本文[^1]\n\n[^1]: 注[^2]\n[^2]: 注の注\n"
+        );
+    }
+
+    #[test]
+    fn quote() {
+        let src = "#quote[引用]と．\n\n#quote(block: true, attribution: [Gauss])[女王．\n\n- 数論]";
+        assert_eq!(
+            convert(src),
+            "“引用”と．\n\n> 女王．\n> \n> - 数論\n> \n> — Gauss\n"
+        );
+        let output = convert_with(
+            "#quote(block: true)[女王．] <q>\n\n次．",
+            FromTypstOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output.syntax.compose(), "> 女王．\n\n次．\n");
+        assert!(matches!(
+            output.warnings[..],
+            [FromTypstWarning {
+                kind: FromTypstWarningKind::LabelIgnored,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn enum_numbering() {
+        let src =
+            "#set enum(numbering: \"(i)\")\n+ あ\n5. い\n+ う\n\n#enum(numbering: \"a)\")[え]";
+        assert_eq!(convert(src), "(i) あ\n\n(v) い\n\n(vi) う\n\na) え\n");
+        assert_eq!(
+            convert("#enum(numbering: \"I.\")[あ][い]"),
+            "I.  あ\n\nII.  い\n"
+        );
+    }
+
+    #[test]
+    fn env_names() {
+        assert_eq!(convert("#bl[内容]"), "&&&\n内容\n&&&\n");
+        assert_eq!(convert("#lemma[補題]"), "&&&lem\n補題\n&&&\n");
+        assert_eq!(
+            error_kind("#bl(foo: 1)[内容]").to_string(),
+            "unknown named argument `foo` of `bl`"
+        );
+        assert!(matches!(
+            error_kind("#block(inset: 4pt)[内容]"),
+            FromTypstErrorKind::UnsupportedFuncCall(_)
+        ));
+    }
+
+    #[test]
+    fn matrices() {
+        assert_eq!(
+            convert("$mat(1, 2; 3, 4) vec(a, b, delim: \"[\")$"),
+            "$\\begin{pmatrix}1 & 2 \\\\ 3 & 4\\end{pmatrix}\\begin{bmatrix}a \\\\ b\\end{bmatrix}$\n"
+        );
+        assert_eq!(
+            convert("$mat(a, b; c, d, augment: #1)$"),
+            "$\\left(\\begin{array}{c|c}a & b \\\\ c & d\\end{array}\\right)$\n"
+        );
+        assert_eq!(convert("$binom(n, k)$"), "$\\binom{n}{k}$\n");
+    }
+
+    #[test]
+    fn accents() {
+        assert_eq!(
+            convert("$hat(x) hat(x y) dot.double(a) accent(v, ->)$"),
+            "$\\hat{x}\\widehat{xy}\\ddot{a}\\vec{v}$\n"
+        );
+        assert_eq!(
+            convert("$underbrace(x, n) cancel(y, cross: #true)$"),
+            "$\\underbrace{x}_{n}\\xcancel{y}$\n"
+        );
+    }
+
+    #[test]
+    fn delimiters() {
+        assert_eq!(
+            convert("$abs(x) floor(y) {x mid(|) y}$"),
+            "$\\left|x\\right|\\left\\lfloor y\\right\\rfloor\\left\\{x\\middle|y\\right\\}$\n"
+        );
+        assert_eq!(
+            convert("$lr([a mid(|) b], size: #150%)$"),
+            "$\\Bigl[a\\Bigm|b\\Bigr]$\n"
+        );
+    }
+
+    #[test]
+    fn styles() {
+        assert_eq!(
+            convert("$bold(upright(x)) bold(sans(A)) italic(upright(x)) display(x)$"),
+            "$\\mathbf{x}\\boldsymbol{\\mathsf{A}}\\mathrm{x}{\\displaystyle x}$\n"
+        );
+        assert_eq!(
+            convert("$root(3, x) frac(a, b)$"),
+            "$\\sqrt[3]{x}\\frac{a}{b}$\n"
+        );
+    }
+
+    #[test]
+    fn application() {
+        assert_eq!(
+            convert("$phi(a, b) zeta(1/2)$"),
+            "$\\phi\\left(a,b\\right)\\zeta\\left(\\frac{1}{2}\\right)$\n"
+        );
+    }
+
+    #[test]
+    fn attachments() {
+        assert_eq!(
+            convert("$f'(x) f''_i^2 attach(X, tl: a, bl: b, br: c)$"),
+            "$f'\\left(x\\right)f''^{2}_{i}{}^{a}_{b}X_{c}$\n"
+        );
+    }
+
+    #[test]
+    fn paren_stripping() {
+        // only the outermost parentheses of an operand are dropped, as Typst renders it
+        assert_eq!(
+            convert("$(a+b)/c ((a))/b (a)(b)/c x^(n+1) √(x+1)$"),
+            "$\\frac{a+b}{c}\\frac{\\left(a\\right)}{b}\\left(a\\right)\\frac{b}{c}x^{n+1}\\sqrt{x+1}$\n"
+        );
+        assert_eq!(
+            convert("$root([a], x)$"),
+            "$\\sqrt[{\\left[a\\right]}]{x}$\n"
+        );
+    }
+
+    #[test]
+    fn math_text() {
+        assert_eq!(
+            convert("$x \"if\" y, \"ただし\" z, \"a_b & c\"$"),
+            "$x\\mathrm{if}y,\\text{ただし}z,\\text{a\\_b \\& c}$\n"
+        );
+        assert_eq!(convert("$\"a^b\"$"), "$\\text{a\\textasciicircum{}b}$\n");
+        let options = compose::ComposeOptions {
+            math_text: compose::MathTextMode::Mathrm,
+        };
+        assert_eq!(
+            convert_with("$\"a^b\"$", FromTypstOptions::default())
+                .unwrap()
+                .syntax
+                .compose_with(options),
+            "$\\mathrm{a{\\char\"5E}b}$\n"
+        );
+    }
+
+    #[test]
+    fn display_math() {
+        assert_eq!(convert("$ x $"), "$$\nx\n$$\n");
+        assert_eq!(
+            convert("$ a &= b \\ &= c $"),
+            "\\begin{align*}\na&=b\\\\\n&=c\n\\end{align*}\n"
+        );
+        let src = "#set math.equation(numbering: \"(1)\")\n$ E = m c^2 $ <emc>\n@emc";
+        assert_eq!(
+            convert(src),
+            "\\begin{equation*}\nE=mc^{2}\\tag{1}\n\\end{equation*}\n\n(1)\n"
+        );
+        let src = "#set math.equation(numbering: \"1.\")\n$ a \\ b $\n$ a & = b $";
+        assert_eq!(
+            convert(src),
+            "\\begin{gather*}\na\\\\\nb\\tag*{1.}\n\\end{gather*}\n\n\
+             \\begin{align*}\na&=b\\tag*{2.}\n\\end{align*}\n"
+        );
+        assert_eq!(
+            convert("$ a \\ b $"),
+            "\\begin{gather*}\na\\\\\nb\n\\end{gather*}\n"
+        );
+        assert!(matches!(
+            error_kind("$ x $ <eq>\n\n@eq"),
+            FromTypstErrorKind::UnnumberedRef(label) if label == "eq"
+        ));
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            convert("$\"supp\"_R f op(\"argmax\", limits: #true)_x$"),
+            "$\\operatorname{supp}_{R}f\\operatorname*{argmax}_{x}$\n"
+        );
+        assert_eq!(
+            convert("$limits(A)_x class(\"relation\", x)$"),
+            "$\\mathop{A}\\limits_{x}\\mathrel{x}$\n"
+        );
+    }
+
+    /// The source text under the span of the conversion error.
+    fn error_text(src: &str) -> String {
+        let source = typst::Source::detached(src);
+        let err = convert_source(&source, FromTypstOptions::default()).unwrap_err();
+        source.text()[source.range(err.span).unwrap()].to_string()
+    }
+
+    #[test]
+    fn error_spans() {
+        assert_eq!(error_text("#enum(start: -1)[a]"), "-1");
+        assert_eq!(error_text("$frac(a, b, c)$"), "c");
+        assert_eq!(error_text("$x #foo$"), "foo");
+        assert_eq!(error_text("= A\n\n@missing"), "@missing");
+        assert_eq!(
+            error_text("本文#footnote(<missing>)"),
+            "footnote(<missing>)"
+        );
+        assert_eq!(
+            error_text("#table(columns: 2, table.cell(colspan: 2)[a])"),
+            "colspan: 2"
+        );
+        assert_eq!(error_text("#enum(numbering: 1)[a]"), "1");
+        assert_eq!(error_text("$lr(\\{ x \\}, size: #1pt)$"), "1pt");
+        assert_eq!(error_text("$lr(\\{ x \\}, size: #none)$"), "none");
+        assert_eq!(error_text("#table(columns: 1, align: middle)[a]"), "middle");
+        assert_eq!(error_text("#table(columns: 1, align: 1)[a]"), "1");
+        assert_eq!(error_text("$class(\"foo\", x)$"), "\"foo\"");
+        assert_eq!(error_text("#quote(block: 1)[a]"), "1");
+    }
+
+    fn error_kind(src: &str) -> FromTypstErrorKind {
+        convert_with(src, FromTypstOptions::default())
+            .unwrap_err()
+            .kind
+    }
+
+    #[test]
+    fn errors() {
+        use FromTypstErrorKind::*;
+        assert!(matches!(error_kind("#thm()"), MissingEnvBody(env) if env == "thm"));
+        assert!(matches!(
+            error_kind("#thm(title: [a\n\nb])[c]"),
+            InvalidTitle(_)
+        ));
+        assert!(matches!(error_kind("#thm(foo: 1)[a]"), UnknownNamedArg(_, arg) if arg == "foo"));
+        assert!(matches!(error_kind("#thm(..x)"), SpreadArgUnsupported(_)));
+        assert!(matches!(error_kind("#thm(1)"), NotContent(_)));
+        assert!(matches!(error_kind("#box[a]"), UnsupportedFuncCall(_)));
+        assert!(matches!(
+            error_kind("#calc.pow(2, 3)"),
+            UnsupportedCallee(_)
+        ));
+        assert!(matches!(error_kind("$#(x).b$"), InvalidFieldAccess(_)));
+    }
+
+    #[test]
+    fn lenient() {
+        let src = "#thm[a #box[b]]\n\n$ x #h(1em) y #box[c] $\n\n#enum([d], 1)\n\n@nope";
+        let output = convert_with(src, FromTypstOptions { lenient: true }).unwrap();
+        assert_eq!(
+            output.syntax.compose(),
+            "&&&thm\na<!-- #box[b] -->\n&&&\n\n\
+             $$\nx\\text{⟨unsupported: \\#h(1em)⟩}y\\text{⟨unsupported: \\#box[c]⟩}\n$$\n\n\
+             <!-- #enum([d], 1) -->\n\n@nope\n"
+        );
+        let errors = output
+            .errors
+            .iter()
+            .map(|err| err.kind.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "unsupported function call: box",
+                "unsupported function call: h",
+                "unsupported function call: box",
+                "expected content, a string or a function call, found integer",
+                "unresolved labels: nope"
+            ]
+        );
+    }
+
+    #[test]
+    fn warnings() {
+        let src = "#set text(lang: \"ja\")\n#set enum(indent: 1em)\n#show: it => it\n\
+                   see $x$ here. 定理 $y$ です。*単項式* (_monomial_)\n\n\
+                   #enum(indent: 1em)[a]\n\n$mat(1, 2; 3, 4; gap: #1em)$";
+        let output = convert_with(src, FromTypstOptions::default()).unwrap();
+        let warnings = output
+            .warnings
+            .iter()
+            .map(|warning| warning.kind.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            [
+                "this set rule is ignored",
+                "argument `indent` of set rules for `enum` is ignored",
+                "show rules are ignored",
+                "the space is dropped and the words around it are joined",
+                "the space is dropped and the words around it are joined",
+                "argument `indent` of `enum` is ignored",
+                "argument `gap` of `mat` is ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn term_list() {
+        let src = "/ 環: 加法と乗法を持つ集合．\n  続き．\n/ 体: 可換な環．";
+        assert_eq!(
+            convert(src),
+            "**環**\n    加法と乗法を持つ集合．続き．\n\n**体**\n    可換な環．\n"
+        );
+    }
+
+    #[test]
+    fn code() {
+        assert_eq!(
+            convert("コード `x + 1` と ``` `a` ``` ．"),
+            "コード`x + 1`と`` `a` ``．\n"
+        );
+        assert_eq!(
+            convert("```rust\nfn main() {}\n```"),
+            "```rust\nfn main() {}\n```\n"
+        );
+        assert_eq!(convert("````\n```\n````"), "````\n```\n````\n");
     }
 }
@@ -1,6 +1,8 @@
+pub mod assets;
 pub mod ast;
 pub mod compose;
 pub mod dictionary;
 pub mod from_typst;
+pub mod resolve;
 
 pub use dictionary::*;
@@ -0,0 +1,160 @@
+//! The reference resolution pass, which fills the `number` of [`MathDisplay`], [`Footnote`]
+//! and [`Figure`] and the `resolved` of [`Ref`] and [`Link`]. They are `None` before.
+
+use super::ast::*;
+use crate::utils::{numbering, numbering_pattern};
+
+use std::collections::{HashMap, HashSet};
+
+//
+
+/// The labels of references which could not be resolved.
+#[derive(Debug, Default)]
+pub struct UnresolvedRefs {
+    /// Labels which no element has.
+    pub missing: Vec<String>,
+    /// Labels of elements which have no number to refer to them by, such as headings without
+    /// `#set heading(numbering: ..)`.
+    pub unnumbered: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    headings: Vec<usize>,
+    by_name: HashMap<&'static str, usize>,
+    equations: usize,
+}
+
+impl Counters {
+    fn heading(&mut self, depth: usize) -> String {
+        self.headings.resize(depth, 0);
+        self.headings[depth - 1] += 1;
+        self.headings
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    fn env(&mut self, kind: &EnvKind) -> Option<String> {
+        let counter = self.by_name.entry(kind.counter()?).or_insert(0);
+        *counter += 1;
+        Some(counter.to_string())
+    }
+
+    fn figure(&mut self, kind: FigureKind) -> String {
+        let counter = self.by_name.entry(kind.counter()).or_insert(0);
+        *counter += 1;
+        counter.to_string()
+    }
+
+    fn footnote(&mut self) -> String {
+        let counter = self.by_name.entry("footnote").or_insert(0);
+        *counter += 1;
+        counter.to_string()
+    }
+
+    fn equation(&mut self, pattern: &str) -> String {
+        self.equations += 1;
+        match numbering_pattern(pattern, 0) {
+            Some((prefix, symbol, suffix)) => {
+                format!("{}{}{}", prefix, numbering(symbol, self.equations), suffix)
+            }
+            None => format!("({})", self.equations),
+        }
+    }
+}
+
+impl Syntax {
+    /// Numbers headings, environments, figures, footnotes and display equations,
+    /// and resolves every `@label` and `#link(<label>)`.
+    /// Links with text only need the label to exist.
+    pub fn resolve_refs(&mut self) -> Result<(), UnresolvedRefs> {
+        let mut counters = Counters::default();
+        let mut targets = HashMap::<String, RefTarget>::new();
+        let mut labels = HashSet::<String>::new();
+        self.visit_mut(&mut |segment| {
+            let target = match segment {
+                Segment::Heading(heading) if heading.numbered => Some(RefTarget {
+                    supplement: "節".to_string(),
+                    number: counters.heading(heading.depth),
+                    is_footnote: false,
+                }),
+                Segment::Env(env) => counters.env(&env.kind).map(|number| RefTarget {
+                    supplement: env.kind.supplement().to_string(),
+                    number,
+                    is_footnote: false,
+                }),
+                Segment::Footnote(footnote) => {
+                    let number = counters.footnote();
+                    footnote.number = Some(number.clone());
+                    Some(RefTarget {
+                        supplement: String::new(),
+                        number,
+                        is_footnote: true,
+                    })
+                }
+                Segment::Figure(figure) if figure.numbered => {
+                    let number = counters.figure(figure.kind);
+                    figure.number = Some(number.clone());
+                    Some(RefTarget {
+                        supplement: figure.kind.supplement().to_string(),
+                        number,
+                        is_footnote: false,
+                    })
+                }
+                // the composer tags numbered equations
+                Segment::MathDisplay(MathDisplay {
+                    numbering: Some(pattern),
+                    number: slot,
+                    ..
+                }) => {
+                    let number = counters.equation(pattern);
+                    *slot = Some(number.clone());
+                    Some(RefTarget {
+                        supplement: String::new(),
+                        number,
+                        is_footnote: false,
+                    })
+                }
+                _ => None,
+            };
+            if let Some(Some(label)) = segment.label_mut() {
+                labels.insert(label.clone());
+                if let Some(target) = target {
+                    targets.insert(label.clone(), target);
+                }
+            }
+        });
+
+        let mut unresolved = UnresolvedRefs::default();
+        self.visit_mut(&mut |segment| {
+            let (target, resolved, needs_number) = match segment {
+                Segment::Ref(ref_) => (&ref_.target, &mut ref_.resolved, true),
+                Segment::Link(Link {
+                    dest: LinkDest::Label(label),
+                    content,
+                    resolved,
+                }) => (&*label, resolved, content.is_none()),
+                _ => return,
+            };
+            *resolved = targets.get(target).cloned();
+            let list = if !labels.contains(target) {
+                &mut unresolved.missing
+            } else if resolved.is_none() && needs_number {
+                &mut unresolved.unnumbered
+            } else {
+                return;
+            };
+            if !list.contains(target) {
+                list.push(target.clone());
+            }
+        });
+
+        if unresolved.missing.is_empty() && unresolved.unnumbered.is_empty() {
+            Ok(())
+        } else {
+            Err(unresolved)
+        }
+    }
+}
@@ -12,6 +12,7 @@ where
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn _test() {
     let v = vec!["a", "b", "c"];
     let s = v.iter().map(|s| s.to_string()).intercalate(", ");
@@ -32,6 +33,23 @@ pub fn add_indent(s: &str, init_indent_num: usize, rest_indent_num: usize) -> St
         .intercalate("\n")
 }
 
+/// Returns a backtick fence longer than any run of backticks in `code`, and at least `min` long.
+pub fn code_fence(code: &str, min: usize) -> String {
+    let longest = code
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    "`".repeat(std::cmp::max(longest + 1, min))
+}
+
+#[test]
+fn _test_code_fence() {
+    assert_eq!(code_fence("x", 1), "`");
+    assert_eq!(code_fence("a ``b`` c", 1), "```");
+    assert_eq!(code_fence("```rust\n```", 3), "````");
+}
+
 //
 
 pub fn roman(i: usize) -> String {
@@ -86,3 +104,60 @@ pub fn roman(i: usize) -> String {
     }
     s
 }
+
+/// `a`, ..., `z`, `aa`, `ab`, ... as Typst counts with the numbering symbol `a`.
+pub fn alpha(i: usize) -> String {
+    if i == 0 {
+        return "-".to_string();
+    }
+    let mut s = Vec::new();
+    let mut i = i;
+    while i > 0 {
+        i -= 1;
+        s.push((b'a' + (i % 26) as u8) as char);
+        i /= 26;
+    }
+    s.iter().rev().collect()
+}
+
+/// Splits a Typst numbering pattern such as `"(i)"` into the prefix, the counting symbol and the
+/// suffix used for a list item at nesting `depth`, following Typst's `NumberingPattern::apply_kth`.
+/// Returns `None` if the pattern has no counting symbol.
+pub fn numbering_pattern(pattern: &str, depth: usize) -> Option<(String, char, String)> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for c in pattern.chars() {
+        if matches!(c, '1' | 'a' | 'A' | 'i' | 'I') {
+            pieces.push((std::mem::take(&mut current), c));
+        } else {
+            current.push(c);
+        }
+    }
+    let prefix = pieces.first()?.0.clone();
+    let symbol = pieces.get(depth).or(pieces.last())?.1;
+    Some((prefix, symbol, current))
+}
+
+pub fn numbering(symbol: char, i: usize) -> String {
+    match symbol {
+        'a' => alpha(i),
+        'A' => alpha(i).to_uppercase(),
+        'i' => roman(i).to_lowercase(),
+        'I' => roman(i),
+        _ => i.to_string(),
+    }
+}
+
+#[test]
+fn _test_numbering() {
+    assert_eq!(alpha(28), "ab");
+    assert_eq!(
+        numbering_pattern("(i)", 0),
+        Some(("(".to_string(), 'i', ")".to_string()))
+    );
+    assert_eq!(
+        numbering_pattern("1.a)", 1),
+        Some(("".to_string(), 'a', ")".to_string()))
+    );
+    assert_eq!(numbering('A', 3), "C");
+}